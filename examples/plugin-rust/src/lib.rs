@@ -1,27 +1,26 @@
-extern crate libloe;
+use loe_plugin::{export_plugin, Buffer, Plugin, PluginResult};
 
-use libloe::{buffer::Buffer, plugin::*};
+#[derive(Default)]
+struct Greeter;
 
-// returns the plugins name
-#[no_mangle]
-extern fn name() -> &'static str {
-    "plugin-rust"
-}
+impl Plugin for Greeter {
+    // returns the plugins name
+    fn name(&self) -> &str {
+        "plugin-rust"
+    }
 
-// returns the commands we want to subscribe to
-#[no_mangle]
-extern fn commands() -> Vec<String> {
-    vec!["greet".to_string()]
-}
+    // returns the commands we want to subscribe to
+    fn commands(&self) -> Vec<String> {
+        vec!["greet".to_string()]
+    }
+
+    // called when a command is about to be executed
+    fn dispatch(&mut self, buffer: &mut Buffer, _cmd: &str) -> PluginResult<()> {
+        buffer.set_line(0, "hello from so!")
+    }
 
-// called when a command is about to be executed
-#[no_mangle]
-extern fn dispatch(buffer: &mut Buffer, _cmd: &str) -> DispatchResult {
-    let line = buffer.content.get_mut(0).unwrap();
-    *line = "hello from so!".to_string();
-    Ok(())
+    // called when the plugin gets destroyed
+    fn unload(&mut self) {}
 }
 
-// called when the plugin gets destroyed
-#[no_mangle]
-extern fn unload() {}
+export_plugin!(Greeter);
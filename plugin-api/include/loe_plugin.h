@@ -0,0 +1,100 @@
+/* c interface for loe plugins, mirrors `src/abi.rs`.
+ *
+ * memory rules:
+ * - the side that allocates memory is the only one allowed to free it
+ * - strings handed to the plugin (commands, event data, lines) are borrowed
+ *   and only valid until the call returns or the buffer is modified
+ * - strings returned by the plugin (manifest, commands, events, last error) are
+ *   owned by the plugin and have to stay valid until `loe_plugin_unload` or,
+ *   for the last error, until the next call into the plugin
+ * - the host copies everything it wants to keep
+ */
+
+#ifndef LOE_PLUGIN_H
+#define LOE_PLUGIN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define LOE_PLUGIN_ABI_VERSION 1
+
+typedef int32_t loe_status;
+
+#define LOE_OK 0
+#define LOE_ERROR 1
+#define LOE_INVALID_ARGUMENT 2
+#define LOE_OUT_OF_RANGE 3
+/* the plugin panicked, in this call or an earlier one. the editor disables
+ * the plugin */
+#define LOE_PANICKED 4
+
+typedef struct loe_buffer loe_buffer;
+
+/* utf-8 string that is not necessarily nul-terminated */
+typedef struct loe_str
+{
+    const char *ptr;
+    size_t len;
+} loe_str;
+
+/* new functions are only ever appended, check `size` before using fields
+ * added in later versions */
+typedef struct loe_host_api
+{
+    uint32_t abi_version;
+    size_t size;
+    size_t (*line_count)(const loe_buffer *buffer);
+    loe_status (*get_line)(const loe_buffer *buffer, size_t row, loe_str *line);
+    loe_status (*set_line)(loe_buffer *buffer, size_t row, loe_str line);
+    loe_status (*insert_line)(loe_buffer *buffer, size_t row, loe_str line);
+    loe_status (*remove_line)(loe_buffer *buffer, size_t row);
+    void (*get_cursor)(const loe_buffer *buffer, int64_t *col, int64_t *row);
+    void (*set_cursor)(loe_buffer *buffer, int64_t col, int64_t row);
+    /* decorations belong to the calling plugin. an empty status text removes
+     * the segment, rows and columns start at zero */
+    loe_status (*set_status)(loe_str id, loe_str text);
+    loe_status (*add_sign)(size_t row, loe_str text);
+    loe_status (*add_virtual_text)(size_t row, loe_str text);
+    loe_status (*add_highlight)(size_t row, size_t start, size_t end, loe_str group);
+    void (*clear_decorations)(void);
+    /* background work of the calling plugin. the results arrive as the
+     * events `job-stdout`, `job-stderr`, `job-exit` and `timer`, their
+     * payload starts with the id, which must not contain spaces */
+    loe_status (*start_job)(loe_str id, loe_str command, const loe_str *args, size_t arg_count);
+    loe_status (*stop_job)(loe_str id);
+    loe_status (*start_timer)(loe_str id, uint64_t interval_ms, bool repeat);
+    loe_status (*stop_timer)(loe_str id);
+} loe_host_api;
+
+/* `editor_version` is the minimum editor version and may be null */
+typedef struct loe_manifest
+{
+    uint32_t abi_version;
+    const char *name;
+    const char *version;
+    const char *author;
+    const char *description;
+    const char *editor_version;
+} loe_manifest;
+
+/* required, the abi version is checked before any other symbol is used */
+uint32_t loe_plugin_abi_version(void);
+const loe_manifest *loe_plugin_manifest(void);
+const char *const *loe_plugin_commands(void);
+loe_status loe_plugin_dispatch(const loe_host_api *host, loe_buffer *buffer, const char *cmd);
+
+/* optional */
+const char *const *loe_plugin_events(void);
+loe_status loe_plugin_notify(const loe_host_api *host, loe_buffer *buffer, const char *event,
+                             const char *payload);
+const char *loe_plugin_last_error(void);
+void loe_plugin_unload(void);
+/* `config` is the `[plugin.<name>]` table of the editor configuration as toml */
+loe_status loe_plugin_configure(const char *config);
+/* modes the plugin defines */
+const char *const *loe_plugin_modes(void);
+/* default key bindings, each of the form "<mode> <keys> <command>" */
+const char *const *loe_plugin_bindings(void);
+
+#endif
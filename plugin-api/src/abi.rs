@@ -0,0 +1,158 @@
+// raw interface between the editor and dynamically loaded plugins. every
+// type in here is `#[repr(C)]` and mirrored in `include/loe_plugin.h`.
+//
+// memory rules:
+// - the side that allocates memory is the only one allowed to free it
+// - strings handed to the plugin (commands, event data, lines) are borrowed and
+//   only valid until the call returns or the buffer is modified
+// - strings returned by the plugin (manifest, commands, events, last error) are
+//   owned by the plugin and have to stay valid until `loe_plugin_unload` or,
+//   for the last error, until the next call into the plugin
+// - the host copies everything it wants to keep
+
+use std::os::raw::c_char;
+
+pub const LOE_PLUGIN_ABI_VERSION: u32 = 1;
+
+pub type LoeStatus = i32;
+
+pub const LOE_OK: LoeStatus = 0;
+pub const LOE_ERROR: LoeStatus = 1;
+pub const LOE_INVALID_ARGUMENT: LoeStatus = 2;
+pub const LOE_OUT_OF_RANGE: LoeStatus = 3;
+// returned by a plugin that panicked, in this call or an earlier one. the
+// editor disables the plugin
+pub const LOE_PANICKED: LoeStatus = 4;
+
+// opaque handle to an editor buffer, only valid during the call it was
+// passed to
+#[repr(C)]
+pub struct LoeBuffer
+{
+    _private: [u8; 0],
+}
+
+// utf-8 string that is not necessarily nul-terminated
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LoeStr
+{
+    pub ptr: *const c_char,
+    pub len: usize,
+}
+
+// functions the editor provides to plugins. new functions are only ever
+// appended, plugins can check `size` before using fields added later
+#[repr(C)]
+pub struct LoeHostApi
+{
+    pub abi_version: u32,
+    pub size: usize,
+    pub line_count: extern "C" fn(buffer: *const LoeBuffer) -> usize,
+    pub get_line:
+        extern "C" fn(buffer: *const LoeBuffer, row: usize, line: *mut LoeStr) -> LoeStatus,
+    pub set_line: extern "C" fn(buffer: *mut LoeBuffer, row: usize, line: LoeStr) -> LoeStatus,
+    pub insert_line: extern "C" fn(buffer: *mut LoeBuffer, row: usize, line: LoeStr) -> LoeStatus,
+    pub remove_line: extern "C" fn(buffer: *mut LoeBuffer, row: usize) -> LoeStatus,
+    pub get_cursor: extern "C" fn(buffer: *const LoeBuffer, col: *mut i64, row: *mut i64),
+    pub set_cursor: extern "C" fn(buffer: *mut LoeBuffer, col: i64, row: i64),
+    // decorations belong to the calling plugin. an empty status text removes
+    // the segment, rows and columns start at zero
+    pub set_status: extern "C" fn(id: LoeStr, text: LoeStr) -> LoeStatus,
+    pub add_sign: extern "C" fn(row: usize, text: LoeStr) -> LoeStatus,
+    pub add_virtual_text: extern "C" fn(row: usize, text: LoeStr) -> LoeStatus,
+    pub add_highlight:
+        extern "C" fn(row: usize, start: usize, end: usize, group: LoeStr) -> LoeStatus,
+    pub clear_decorations: extern "C" fn(),
+    // background work of the calling plugin. the results arrive as the
+    // events `job-stdout`, `job-stderr`, `job-exit` and `timer`, their
+    // payload starts with the id, which must not contain spaces
+    pub start_job: extern "C" fn(
+        id: LoeStr,
+        command: LoeStr,
+        args: *const LoeStr,
+        arg_count: usize,
+    ) -> LoeStatus,
+    pub stop_job: extern "C" fn(id: LoeStr) -> LoeStatus,
+    pub start_timer: extern "C" fn(id: LoeStr, interval_ms: u64, repeat: bool) -> LoeStatus,
+    pub stop_timer: extern "C" fn(id: LoeStr) -> LoeStatus,
+}
+
+// metadata every plugin has to export. `editor_version` is the minimum
+// editor version the plugin needs and may be null. all strings are
+// nul-terminated and owned by the plugin
+#[repr(C)]
+pub struct LoePluginManifest
+{
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub author: *const c_char,
+    pub description: *const c_char,
+    pub editor_version: *const c_char,
+}
+
+// symbols exported by a plugin. `abi_version`, `manifest`, `commands` and
+// `dispatch` are required, the others optional. the abi version is checked
+// before any other symbol is touched. string lists are terminated by a null
+// pointer
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+pub type ManifestFn = unsafe extern "C" fn() -> *const LoePluginManifest;
+pub type CommandsFn = unsafe extern "C" fn() -> *const *const c_char;
+pub type DispatchFn = unsafe extern "C" fn(
+    host: *const LoeHostApi,
+    buffer: *mut LoeBuffer,
+    cmd: *const c_char,
+) -> LoeStatus;
+pub type EventsFn = unsafe extern "C" fn() -> *const *const c_char;
+pub type NotifyFn = unsafe extern "C" fn(
+    host: *const LoeHostApi,
+    buffer: *mut LoeBuffer,
+    event: *const c_char,
+    payload: *const c_char,
+) -> LoeStatus;
+pub type LastErrorFn = unsafe extern "C" fn() -> *const c_char;
+pub type UnloadFn = unsafe extern "C" fn();
+// modes the plugin defines, as a string list
+pub type ModesFn = unsafe extern "C" fn() -> *const *const c_char;
+// default key bindings as a string list. each entry has the form
+// `<mode> <keys> <command>` like the arguments of the `map` command
+pub type BindingsFn = unsafe extern "C" fn() -> *const *const c_char;
+// receives the `[plugin.<name>]` table of the editor configuration as toml
+// text, called once right after loading
+pub type ConfigureFn = unsafe extern "C" fn(config: *const c_char) -> LoeStatus;
+
+pub const SYMBOL_ABI_VERSION: &[u8] = b"loe_plugin_abi_version\0";
+pub const SYMBOL_MANIFEST: &[u8] = b"loe_plugin_manifest\0";
+pub const SYMBOL_COMMANDS: &[u8] = b"loe_plugin_commands\0";
+pub const SYMBOL_DISPATCH: &[u8] = b"loe_plugin_dispatch\0";
+pub const SYMBOL_EVENTS: &[u8] = b"loe_plugin_events\0";
+pub const SYMBOL_NOTIFY: &[u8] = b"loe_plugin_notify\0";
+pub const SYMBOL_LAST_ERROR: &[u8] = b"loe_plugin_last_error\0";
+pub const SYMBOL_UNLOAD: &[u8] = b"loe_plugin_unload\0";
+pub const SYMBOL_CONFIGURE: &[u8] = b"loe_plugin_configure\0";
+pub const SYMBOL_MODES: &[u8] = b"loe_plugin_modes\0";
+pub const SYMBOL_BINDINGS: &[u8] = b"loe_plugin_bindings\0";
+
+impl LoeStr
+{
+    pub fn new(s: &str) -> Self
+    {
+        Self {
+            ptr: s.as_ptr() as *const c_char,
+            len: s.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to be valid for reads of `len` bytes.
+    pub unsafe fn as_str<'a>(&self) -> Option<&'a str>
+    {
+        if self.ptr.is_null() {
+            return None;
+        }
+        let bytes = std::slice::from_raw_parts(self.ptr as *const u8, self.len);
+        std::str::from_utf8(bytes).ok()
+    }
+}
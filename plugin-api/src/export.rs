@@ -0,0 +1,311 @@
+// glue between the generated `loe_plugin_*` symbols and a `Plugin`. all
+// strings handed to the editor are kept alive in here until unload
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::abi::*;
+use crate::{Buffer, Plugin, PluginResult};
+
+// null-terminated array of pointers into `_strings`
+struct StringList
+{
+    _strings: Vec<CString>,
+    pointers: Vec<*const c_char>,
+}
+
+// package metadata of the plugin crate, filled in by `export_plugin!`
+pub struct Package
+{
+    pub version: &'static str,
+    pub authors: &'static str,
+    pub description: &'static str,
+}
+
+struct Manifest
+{
+    _strings: Vec<CString>,
+    raw: LoePluginManifest,
+}
+
+pub struct Exported<T>
+{
+    plugin: Mutex<T>,
+    manifest: Manifest,
+    commands: StringList,
+    events: StringList,
+    modes: StringList,
+    bindings: StringList,
+    last_error: Mutex<CString>,
+}
+
+// the raw pointers only point into the owned strings, which are never
+// modified after construction
+unsafe impl<T: Send> Sync for Exported<T> {}
+unsafe impl<T: Send> Send for Exported<T> {}
+
+impl<T> Exported<T>
+where
+    T: Plugin,
+{
+    pub fn new(plugin: T, package: Package) -> Self
+    {
+        Self {
+            manifest: Manifest::new(&plugin, package),
+            commands: StringList::new(plugin.commands()),
+            events: StringList::new(plugin.events()),
+            modes: StringList::new(plugin.modes()),
+            bindings: StringList::new(
+                plugin
+                    .bindings()
+                    .iter()
+                    .map(|binding| format!("{} {} {}", binding.mode, binding.keys, binding.command))
+                    .collect(),
+            ),
+            plugin: Mutex::new(plugin),
+            last_error: Mutex::new(CString::default()),
+        }
+    }
+
+    pub fn manifest(&self) -> *const LoePluginManifest
+    {
+        &self.manifest.raw
+    }
+
+    pub fn commands(&self) -> *const *const c_char
+    {
+        self.commands.as_ptr()
+    }
+
+    pub fn events(&self) -> *const *const c_char
+    {
+        self.events.as_ptr()
+    }
+
+    pub fn modes(&self) -> *const *const c_char
+    {
+        self.modes.as_ptr()
+    }
+
+    pub fn bindings(&self) -> *const *const c_char
+    {
+        self.bindings.as_ptr()
+    }
+
+    pub fn last_error(&self) -> *const c_char
+    {
+        self.last_error.lock().unwrap().as_ptr()
+    }
+
+    /// # Safety
+    ///
+    /// Arguments have to be the pointers handed over by the editor.
+    pub unsafe fn dispatch(
+        &self,
+        host: *const LoeHostApi,
+        buffer: *mut LoeBuffer,
+        cmd: *const c_char,
+    ) -> LoeStatus
+    {
+        if host.is_null() || buffer.is_null() || cmd.is_null() {
+            return LOE_INVALID_ARGUMENT;
+        }
+        let cmd = CStr::from_ptr(cmd).to_string_lossy();
+        let mut buffer = Buffer::from_raw(&*host, buffer);
+        self.guard(|plugin| plugin.dispatch(&mut buffer, &cmd))
+    }
+
+    /// # Safety
+    ///
+    /// Arguments have to be the pointers handed over by the editor.
+    pub unsafe fn notify(
+        &self,
+        host: *const LoeHostApi,
+        buffer: *mut LoeBuffer,
+        event: *const c_char,
+        payload: *const c_char,
+    ) -> LoeStatus
+    {
+        if host.is_null() || event.is_null() {
+            return LOE_INVALID_ARGUMENT;
+        }
+        let event = CStr::from_ptr(event).to_string_lossy();
+        let payload = if payload.is_null() {
+            Default::default()
+        } else {
+            CStr::from_ptr(payload).to_string_lossy()
+        };
+        let mut buffer = if buffer.is_null() {
+            None
+        } else {
+            Some(Buffer::from_raw(&*host, buffer))
+        };
+        self.guard(|plugin| plugin.notify(buffer.as_mut(), &event, &payload))
+    }
+
+    /// # Safety
+    ///
+    /// `config` has to be the pointer handed over by the editor.
+    pub unsafe fn configure(&self, config: *const c_char) -> LoeStatus
+    {
+        if config.is_null() {
+            return LOE_INVALID_ARGUMENT;
+        }
+        let config = CStr::from_ptr(config).to_string_lossy();
+        self.guard(|plugin| plugin.configure(&config))
+    }
+
+    pub fn unload(&self)
+    {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            if let Ok(mut plugin) = self.plugin.lock() {
+                plugin.unload();
+            }
+        }));
+    }
+
+    // panics must not unwind into the editor. they are reported as
+    // `LOE_PANICKED`, just like every call after them that finds the state
+    // poisoned
+    fn guard<F>(&self, call: F) -> LoeStatus
+    where
+        F: FnOnce(&mut T) -> PluginResult<()>,
+    {
+        let result = catch_unwind(AssertUnwindSafe(|| match self.plugin.lock() {
+            Ok(mut plugin) => call(&mut plugin).map_err(|err| (LOE_ERROR, err)),
+            Err(_) => Err((LOE_PANICKED, "plugin state is poisoned".to_string())),
+        }))
+        .unwrap_or_else(|_| Err((LOE_PANICKED, "plugin panicked".to_string())));
+
+        match result {
+            Ok(()) => LOE_OK,
+            Err((status, err)) => {
+                *self.last_error.lock().unwrap() = to_cstring(&err);
+                status
+            }
+        }
+    }
+}
+
+impl Manifest
+{
+    fn new<T: Plugin>(plugin: &T, package: Package) -> Self
+    {
+        let strings = vec![
+            to_cstring(plugin.name()),
+            to_cstring(package.version),
+            to_cstring(package.authors),
+            to_cstring(package.description),
+        ];
+        let editor_version = plugin.editor_version().map(to_cstring);
+        let raw = LoePluginManifest {
+            abi_version: LOE_PLUGIN_ABI_VERSION,
+            name: strings[0].as_ptr(),
+            version: strings[1].as_ptr(),
+            author: strings[2].as_ptr(),
+            description: strings[3].as_ptr(),
+            editor_version: editor_version.as_ref().map_or(ptr::null(), |v| v.as_ptr()),
+        };
+        Self {
+            _strings: strings.into_iter().chain(editor_version).collect(),
+            raw,
+        }
+    }
+}
+
+impl StringList
+{
+    fn new(strings: Vec<String>) -> Self
+    {
+        let strings = strings.iter().map(|s| to_cstring(s)).collect::<Vec<_>>();
+        let pointers = strings
+            .iter()
+            .map(|s| s.as_ptr())
+            .chain(std::iter::once(ptr::null()))
+            .collect();
+        Self {
+            _strings: strings,
+            pointers,
+        }
+    }
+
+    fn as_ptr(&self) -> *const *const c_char
+    {
+        self.pointers.as_ptr()
+    }
+}
+
+// interior nul bytes cannot be represented and are dropped
+fn to_cstring(s: &str) -> CString
+{
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[derive(Default)]
+    struct Panicking;
+
+    impl Plugin for Panicking
+    {
+        fn name(&self) -> &str
+        {
+            "panicking"
+        }
+
+        fn commands(&self) -> Vec<String>
+        {
+            vec![]
+        }
+
+        fn dispatch(&mut self, _buffer: &mut Buffer, _cmd: &str) -> PluginResult<()>
+        {
+            Ok(())
+        }
+
+        fn configure(&mut self, config: &str) -> PluginResult<()>
+        {
+            if config == "panic" {
+                panic!("broken");
+            }
+            Err("invalid configuration".to_string())
+        }
+    }
+
+    fn last_error(exported: &Exported<Panicking>) -> String
+    {
+        unsafe { CStr::from_ptr(exported.last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn reports_panics_apart_from_errors()
+    {
+        let package = Package {
+            version: "",
+            authors: "",
+            description: "",
+        };
+        let exported = Exported::new(Panicking, package);
+        let configure = |config: &str| {
+            let config = CString::new(config).unwrap();
+            unsafe { exported.configure(config.as_ptr()) }
+        };
+
+        assert_eq!(configure("fail"), LOE_ERROR);
+        assert_eq!(last_error(&exported), "invalid configuration");
+
+        assert_eq!(configure("panic"), LOE_PANICKED);
+        assert_eq!(last_error(&exported), "plugin panicked");
+
+        assert_eq!(configure("fail"), LOE_PANICKED);
+        assert_eq!(last_error(&exported), "plugin state is poisoned");
+    }
+}
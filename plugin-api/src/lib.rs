@@ -0,0 +1,339 @@
+// helpers for writing loe plugins in rust on top of the c abi in `abi`.
+// implement `Plugin` for a type and call `export_plugin!` with it to
+// generate all symbols the editor looks for.
+
+pub mod abi;
+pub mod export;
+
+pub use lazy_static;
+
+use std::ptr;
+use std::time::Duration;
+
+use crate::abi::*;
+
+pub type PluginResult<T> = Result<T, String>;
+
+pub trait Plugin: Send
+{
+    fn name(&self) -> &str;
+    // minimum editor version the plugin works with
+    fn editor_version(&self) -> Option<&str>
+    {
+        None
+    }
+    fn commands(&self) -> Vec<String>;
+    fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>;
+    // modes the plugin adds to the editor, switch to them with `mode <name>`
+    fn modes(&self) -> Vec<String>
+    {
+        vec![]
+    }
+    // default key bindings, the user's own bindings take precedence
+    fn bindings(&self) -> Vec<Binding>
+    {
+        vec![]
+    }
+    fn events(&self) -> Vec<String>
+    {
+        vec![]
+    }
+    fn notify(
+        &mut self,
+        _buffer: Option<&mut Buffer>,
+        _event: &str,
+        _payload: &str,
+    ) -> PluginResult<()>
+    {
+        Ok(())
+    }
+    // `config` is the `[plugin.<name>]` table of the editor configuration as
+    // toml text, empty if there is none
+    fn configure(&mut self, _config: &str) -> PluginResult<()>
+    {
+        Ok(())
+    }
+    fn unload(&mut self) {}
+}
+
+// binds `keys` in `mode` to `command`, using the key notation of the
+// `map` command such as `gg` or `<C-w>`
+pub struct Binding
+{
+    pub mode: String,
+    pub keys: String,
+    pub command: String,
+}
+
+impl Binding
+{
+    pub fn new(mode: &str, keys: &str, command: &str) -> Self
+    {
+        Self {
+            mode: mode.to_string(),
+            keys: keys.to_string(),
+            command: command.to_string(),
+        }
+    }
+}
+
+// safe access to the buffer handle the editor passed into a call
+pub struct Buffer<'h>
+{
+    host: &'h LoeHostApi,
+    raw: *mut LoeBuffer,
+}
+
+impl<'h> Buffer<'h>
+{
+    /// # Safety
+    ///
+    /// `host` and `raw` have to be the pointers handed over by the editor
+    /// for the current call.
+    pub unsafe fn from_raw(host: &'h LoeHostApi, raw: *mut LoeBuffer) -> Self
+    {
+        Self { host, raw }
+    }
+
+    pub fn line_count(&self) -> usize
+    {
+        (self.host.line_count)(self.raw)
+    }
+
+    pub fn line(&self, row: usize) -> Option<String>
+    {
+        let mut line = LoeStr::new("");
+        if (self.host.get_line)(self.raw, row, &mut line) != LOE_OK {
+            return None;
+        }
+        unsafe { line.as_str().map(str::to_string) }
+    }
+
+    pub fn set_line(&mut self, row: usize, line: &str) -> PluginResult<()>
+    {
+        check((self.host.set_line)(self.raw, row, LoeStr::new(line)))
+    }
+
+    pub fn insert_line(&mut self, row: usize, line: &str) -> PluginResult<()>
+    {
+        check((self.host.insert_line)(self.raw, row, LoeStr::new(line)))
+    }
+
+    pub fn remove_line(&mut self, row: usize) -> PluginResult<()>
+    {
+        check((self.host.remove_line)(self.raw, row))
+    }
+
+    pub fn cursor(&self) -> (i64, i64)
+    {
+        let (mut col, mut row) = (0, 0);
+        (self.host.get_cursor)(self.raw, &mut col, &mut row);
+        (col, row)
+    }
+
+    pub fn set_cursor(&mut self, col: i64, row: i64)
+    {
+        (self.host.set_cursor)(self.raw, col, row);
+    }
+
+    // status line segment identified by `id`, an empty text removes it
+    pub fn set_status(&mut self, id: &str, text: &str) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.clear_decorations), "decorations")?;
+        check((self.host.set_status)(LoeStr::new(id), LoeStr::new(text)))
+    }
+
+    // short marker shown in the gutter next to the line number
+    pub fn add_sign(&mut self, row: usize, text: &str) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.clear_decorations), "decorations")?;
+        check((self.host.add_sign)(row, LoeStr::new(text)))
+    }
+
+    // text shown after the end of the line, it is not part of the buffer
+    pub fn add_virtual_text(&mut self, row: usize, text: &str) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.clear_decorations), "decorations")?;
+        check((self.host.add_virtual_text)(row, LoeStr::new(text)))
+    }
+
+    // colors the columns `start..end` of the line with a highlight group
+    // such as `error`, `warning` or `info`
+    pub fn add_highlight(
+        &mut self,
+        row: usize,
+        start: usize,
+        end: usize,
+        group: &str,
+    ) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.clear_decorations), "decorations")?;
+        check((self.host.add_highlight)(
+            row,
+            start,
+            end,
+            LoeStr::new(group),
+        ))
+    }
+
+    // removes all decorations of this plugin
+    pub fn clear_decorations(&mut self) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.clear_decorations), "decorations")?;
+        (self.host.clear_decorations)();
+        Ok(())
+    }
+
+    // runs `command` in the background, see `LoeHostApi::start_job`
+    pub fn start_job(&mut self, id: &str, command: &str, args: &[&str]) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.stop_timer), "jobs")?;
+        let args = args.iter().map(|arg| LoeStr::new(arg)).collect::<Vec<_>>();
+        check((self.host.start_job)(
+            LoeStr::new(id),
+            LoeStr::new(command),
+            args.as_ptr(),
+            args.len(),
+        ))
+    }
+
+    pub fn stop_job(&mut self, id: &str) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.stop_timer), "jobs")?;
+        check((self.host.stop_job)(LoeStr::new(id)))
+    }
+
+    pub fn start_timer(&mut self, id: &str, interval: Duration, repeat: bool) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.stop_timer), "timers")?;
+        check((self.host.start_timer)(
+            LoeStr::new(id),
+            interval.as_millis() as u64,
+            repeat,
+        ))
+    }
+
+    pub fn stop_timer(&mut self, id: &str) -> PluginResult<()>
+    {
+        self.require(ptr::addr_of!(self.host.stop_timer), "timers")?;
+        check((self.host.stop_timer)(LoeStr::new(id)))
+    }
+
+    // older editors pass a smaller host api without the fields added later.
+    // `field` is the last one the feature needs
+    fn require<T>(&self, field: *const T, feature: &str) -> PluginResult<()>
+    {
+        let end = field as usize + std::mem::size_of::<T>() - self.host as *const _ as usize;
+        if self.host.size < end {
+            return Err(format!("editor does not support {}", feature));
+        }
+        Ok(())
+    }
+}
+
+fn check(status: LoeStatus) -> PluginResult<()>
+{
+    match status {
+        LOE_OK => Ok(()),
+        LOE_INVALID_ARGUMENT => Err("invalid argument".to_string()),
+        LOE_OUT_OF_RANGE => Err("out of range".to_string()),
+        _ => Err("host error".to_string()),
+    }
+}
+
+// generates the `loe_plugin_*` symbols for a type implementing `Plugin`
+// and `Default`. version, authors and description of the manifest are
+// taken from the `Cargo.toml` of the plugin
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:ty) => {
+        $crate::lazy_static::lazy_static! {
+            static ref LOE_PLUGIN: $crate::export::Exported<$plugin> =
+                $crate::export::Exported::new(
+                    <$plugin as Default>::default(),
+                    $crate::export::Package {
+                        version: env!("CARGO_PKG_VERSION"),
+                        authors: env!("CARGO_PKG_AUTHORS"),
+                        description: env!("CARGO_PKG_DESCRIPTION"),
+                    },
+                );
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_abi_version() -> u32
+        {
+            $crate::abi::LOE_PLUGIN_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_manifest() -> *const $crate::abi::LoePluginManifest
+        {
+            LOE_PLUGIN.manifest()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_commands() -> *const *const std::os::raw::c_char
+        {
+            LOE_PLUGIN.commands()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_dispatch(
+            host: *const $crate::abi::LoeHostApi,
+            buffer: *mut $crate::abi::LoeBuffer,
+            cmd: *const std::os::raw::c_char,
+        ) -> $crate::abi::LoeStatus
+        {
+            LOE_PLUGIN.dispatch(host, buffer, cmd)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_events() -> *const *const std::os::raw::c_char
+        {
+            LOE_PLUGIN.events()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_notify(
+            host: *const $crate::abi::LoeHostApi,
+            buffer: *mut $crate::abi::LoeBuffer,
+            event: *const std::os::raw::c_char,
+            payload: *const std::os::raw::c_char,
+        ) -> $crate::abi::LoeStatus
+        {
+            LOE_PLUGIN.notify(host, buffer, event, payload)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_last_error() -> *const std::os::raw::c_char
+        {
+            LOE_PLUGIN.last_error()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_unload()
+        {
+            LOE_PLUGIN.unload()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_configure(
+            config: *const std::os::raw::c_char,
+        ) -> $crate::abi::LoeStatus
+        {
+            LOE_PLUGIN.configure(config)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_modes() -> *const *const std::os::raw::c_char
+        {
+            LOE_PLUGIN.modes()
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn loe_plugin_bindings() -> *const *const std::os::raw::c_char
+        {
+            LOE_PLUGIN.bindings()
+        }
+    };
+}
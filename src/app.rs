@@ -1,98 +1,259 @@
-use std::sync::mpsc::{channel, Receiver};
-use std::thread;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use signal_hook::consts::SIGTSTP;
+use signal_hook::low_level::emulate_default_handler;
 
 use crate::buffer::Buffer;
-use crate::config::Config;
-use crate::input::{CursorMove::*, Event, Key::*};
+use crate::config::{expand_path, Config, ProcessConfig, INIT_SCRIPT_PATH};
+use crate::decoration::{Decorations, Highlight};
+use crate::event_loop::EventLoop;
+use crate::input::{CursorMove, CursorMove::*, Event, Key, Key::*};
+use crate::job::{JobOutput, Jobs, MIN_TIMER_INTERVAL};
+use crate::keymap::{format_keys, parse_keys, Keymap, Lookup};
 use crate::mode::Mode;
-use crate::plugin::{CommandManager, DynamicPlugin, Plugin, StandardPlugin};
-use crate::view::View;
+use crate::options::{
+    parse_set_argument, OptionScope, OptionSpec, OptionValue, Options, SetAction,
+};
+use crate::plugin::{
+    name_from_path, CommandManager, Detached, DynamicPlugin, KeyBinding, Plugin, PluginEvent,
+    PluginRequest, PluginResult, ProcessPlugin, ScriptPlugin, StandardPlugin, WasmPlugin,
+    BUILTIN_SOURCE,
+};
+use crate::theme::{Theme, DEFAULT_THEME};
+use crate::view::{screen_rows, RenderOptions, View};
+
+type Builtin = fn(&mut App, Vec<&str>);
+
+// the commands of the editor itself. plugin commands of the same name are
+// rejected unless they are listed in `plugins.override`
+const BUILTIN_COMMANDS: &[(&str, Builtin)] = &[
+    ("q", |app, _| app.set_mode(Mode::Exit)),
+    ("w", |app, _| app.write_buffer()),
+    ("cancel", |app, _| {
+        app.pending_keys.clear();
+        app.set_mode(Mode::View);
+    }),
+    ("colorscheme", |app, args| {
+        app.colorscheme(args.first().cloned())
+    }),
+    ("config", |app, _| app.show_config()),
+    ("complete", |app, _| app.complete_command()),
+    ("cursor-down", |app, _| app.move_cursor(Relative(0, 1))),
+    ("cursor-left", |app, _| app.move_cursor(Relative(-1, 0))),
+    ("cursor-right", |app, _| app.move_cursor(Relative(1, 0))),
+    ("cursor-up", |app, _| app.move_cursor(Relative(0, -1))),
+    ("indent", |app, _| app.indent()),
+    ("line-end", |app, _| app.move_cursor(CurrentRow(i64::MAX))),
+    ("line-start", |app, _| app.move_cursor(CurrentRow(0))),
+    ("map", |app, args| app.map_keys(args)),
+    ("mode", |app, args| app.mode_command(args.first().cloned())),
+    ("plugin", |app, args| app.plugin_command(args)),
+    ("plugins", |app, _| app.list_plugins()),
+    ("page-down", |app, _| app.scroll_page(1)),
+    ("page-up", |app, _| app.scroll_page(-1)),
+    ("set", |app, args| app.set_options(args, false)),
+    ("setlocal", |app, args| app.set_options(args, true)),
+    ("unmap", |app, args| app.unmap_keys(args)),
+];
 
 pub struct App
 {
     pub(crate) mode: Mode,
     pub(crate) command_buffer: String,
+    pending_keys: Vec<Key>,
+    pending_deadline: Option<Instant>,
     config: Config,
     buffer: Option<Buffer>,
     command_manager: CommandManager,
+    messages: Vec<String>,
+    options: Options,
+    keymap: Keymap,
+    // modes defined by plugins and the plugin that defined them
+    plugin_modes: HashMap<String, String>,
+    decorations: Decorations,
+    jobs: Jobs,
     margin: (i64, i64),
+    scroll_row: usize,
     events: Receiver<Event>,
+    // the thread reading terminal input holds on to the terminal, the event
+    // loop has to be stopped for the terminal to be restored when the app is
+    // dropped
+    event_loop: EventLoop,
+    // modification time of the file after the last save, changes of the
+    // watched file made by the app itself are ignored
+    saved_at: Option<SystemTime>,
     view: View,
 }
 
 impl App
 {
-    pub fn new(config: Config) -> Self
+    // sets up the app with what `config` asks for. nothing is read from the
+    // environment of the user, see `with_environment`
+    pub fn new(config: Config, view: View) -> Self
     {
         let (sender, receiver) = channel();
+        let (options, option_errors) = Options::from_table(&config.options);
+        let (keymap, keymap_errors) = Keymap::from_config(&config.keymap);
+        let mut errors = config.errors.clone();
+        errors.extend(option_errors);
+        errors.extend(keymap_errors);
 
+        let event_loop = EventLoop::new(sender.clone());
         let mut app = Self {
             config,
             buffer: None,
             command_buffer: String::new(),
+            pending_keys: vec![],
+            pending_deadline: None,
             command_manager: CommandManager::new(),
+            messages: errors,
+            options,
+            keymap,
+            plugin_modes: HashMap::new(),
+            decorations: Decorations::new(),
+            jobs: Jobs::new(sender, event_loop.timers()),
             margin: (5, 0),
+            scroll_row: 0,
             events: receiver,
+            event_loop,
+            saved_at: None,
             mode: Mode::View,
-            view: View::new(),
+            view,
         };
 
-        let r = app.view.terminal();
-        thread::spawn(move || loop {
-            if let Some(event) = r.listen() {
-                sender.send(event).ok();
-            }
-        });
+        app.event_loop.listen(app.view.terminal());
 
+        let builtins = BUILTIN_COMMANDS
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        app.command_manager.reserve(&builtins);
         app.command_manager
-            .add_plugin(StandardPlugin::load())
+            .allow_override(&app.config.plugins.overrides);
+        app.command_manager
+            .set_priority(app.config.plugins.priority.clone());
+
+        let standard = StandardPlugin::load();
+        let commands = standard.commands();
+        app.command_manager
+            .add_plugin(standard, BUILTIN_SOURCE)
             .unwrap();
+        app.map_plugin_commands(commands);
 
-        if let Some(plugin_dir_path) = app.config.plugin_path.as_ref() {
+        if let Some(plugin_dir_path) = app.config.plugin_path.clone() {
             log!("plugin_path: {}", plugin_dir_path);
             if let Ok(plugin_dir) = std::fs::read_dir(plugin_dir_path) {
-                for plugin in plugin_dir {
-                    if plugin.is_err() {
-                        log!("skipping plugin");
-                        continue;
+                // load order must not depend on the file system
+                let mut paths = plugin_dir
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        DynamicPlugin::is_library(path)
+                            || WasmPlugin::is_module(path)
+                            || ScriptPlugin::is_script(path)
+                    })
+                    .collect::<Vec<_>>();
+                paths.sort();
+                for path in paths {
+                    if let Err(err) = app.load_plugin(&path) {
+                        app.messages.push(err);
                     }
-                    let plugin_path = plugin.unwrap().path();
-                    let (plugin_name, plugin_state) =
-                        if let Ok(plugin) = DynamicPlugin::load(plugin_path.as_path()) {
-                            let name = (*plugin).name();
-                            let added = app.command_manager.add_plugin(plugin);
-                            (name, if added.is_ok() { "okay" } else { "failed" })
-                        } else {
-                            ("<noname>", "failed")
-                        };
-                    log!("loading plugin {}: {:?}", plugin_name, plugin_state);
                 }
             } else {
                 log!("could not load plugin_path");
             }
         }
 
+        for process in app.config.plugins.process.clone() {
+            if let Err(err) = app.spawn_plugin(&process) {
+                app.messages.push(err);
+            }
+        }
+
+        // requests made while loading take effect before the first frame
+        app.handle_plugin_requests();
         app
     }
 
-    pub fn with_args(mut self, mut args: std::env::Args) -> Self
+    // takes over what belongs to the process and the user: signals, themes
+    // in the config directory and the init script. left out when the app
+    // runs on a terminal of its own, e.g. in tests
+    pub fn with_environment(mut self) -> Self
     {
-        if let Some(arg) = args.nth(1) {
-            self.buffer = Buffer::load(&arg).or_else(|_| Buffer::create(&arg)).ok();
+        self.event_loop.watch_signals();
+
+        // the view starts with the bundled default theme and keeps it if the
+        // configured one or a `default` of the user cannot be loaded
+        let colorscheme = self.config.colorscheme.as_deref().unwrap_or(DEFAULT_THEME);
+        match Theme::load(colorscheme) {
+            Ok(theme) => self.view.set_theme(theme),
+            Err(err) => self
+                .messages
+                .push(format!("{}, using the bundled default colorscheme", err)),
+        }
+
+        let init_script = expand_path(INIT_SCRIPT_PATH);
+        if init_script.exists() {
+            if let Err(err) = self.load_plugin(&init_script) {
+                self.messages.push(err);
+            }
+        }
+
+        // e.g. messages or mappings of the init script
+        self.handle_plugin_requests();
+        self
+    }
+
+    pub fn with_args(self, mut args: std::env::Args) -> Self
+    {
+        match args.nth(1) {
+            Some(arg) => self.open(&arg),
+            None => self,
+        }
+    }
+
+    // loads the file at `path` or starts a new one there
+    pub fn open(mut self, path: &str) -> Self
+    {
+        self.buffer = Buffer::load(path).or_else(|_| Buffer::create(path)).ok();
+        if let Some(buffer) = &self.buffer {
+            let path = buffer.source_path().clone();
+            if let Some(path) = &path {
+                if let Err(err) = self.event_loop.watch(path) {
+                    log!("not watching `{}`: {}", path.display(), err);
+                }
+            }
+            self.emit(PluginEvent::BufferOpened(path));
         }
         self
     }
 
     pub fn run(&mut self) -> Result<(), &'static str>
     {
+        // plugins and the init script have defined their modes by now
+        let defined = self
+            .plugin_modes
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let unknown = self.keymap.unknown_modes(&defined);
+        self.messages.extend(unknown);
+
+        self.emit(PluginEvent::Startup);
         loop {
             self.render();
             self.wait_for_event()?;
+            self.handle_plugin_requests();
 
             if let Mode::Exit = self.mode {
                 break;
             }
         }
+        self.emit(PluginEvent::Shutdown);
+        self.command_manager.shutdown();
         self.view.clear();
         self.view.present();
         Ok(())
@@ -102,34 +263,96 @@ impl App
     {
         self.view.clear();
         if let Some(buffer) = &mut self.buffer {
-            let _color = (rustbox::Color::White, rustbox::Color::Black);
             let (w, h) = {
                 let size = self.view.size();
-                (size.0, size.1 - 1)
+                (size.0, size.1 - 1 - self.messages.len().min(size.1 - 1))
             };
 
+            let cursor_pos = buffer.get_cursor();
+            let cursor_row = cursor_pos.1 as usize;
+            let options = &self.options;
+            let flag = |name| options.get(name).is_some_and(OptionValue::as_bool);
+            let render_options = RenderOptions {
+                number: flag("number"),
+                relativenumber: flag("relativenumber"),
+                list: flag("list"),
+                wrap: flag("wrap"),
+                cursor_row,
+                decorations: &self.decorations,
+            };
+            let margin = if render_options.number || render_options.relativenumber {
+                self.margin.0
+            } else {
+                0
+            } + self.decorations.sign_width() as i64;
+            let width = (w as i64 - margin).max(1) as usize;
+            // how many screen rows the buffer line `row` takes up
+            let rows = |row: usize| match buffer.get_row_at(row) {
+                Some(line) if render_options.wrap => screen_rows(line, width, render_options.list),
+                Some(_) => 1,
+                None => 0,
+            };
+
+            let scrolloff = options
+                .get("scrolloff")
+                .map_or(0, OptionValue::as_number)
+                .max(0) as usize;
+            let scrolloff = scrolloff.min(h.saturating_sub(1) / 2);
+            if cursor_row < self.scroll_row + scrolloff {
+                self.scroll_row = cursor_row.saturating_sub(scrolloff);
+            } else if cursor_row + scrolloff >= self.scroll_row + h.max(1) {
+                self.scroll_row = cursor_row + scrolloff + 1 - h.max(1);
+            }
+            // wrapped lines above the cursor can still push it off the screen
+            while self.scroll_row < cursor_row
+                && (self.scroll_row..=cursor_row + scrolloff)
+                    .map(&rows)
+                    .sum::<usize>()
+                    > h
             {
-                let area = (self.margin.0 as usize, 0usize, w, h);
-                let lines_range = (0..h)
+                self.scroll_row += 1;
+            }
+
+            {
+                let area = (margin as usize, 0usize, w, h);
+                let lines_range = (self.scroll_row..self.scroll_row + h)
                     .map(|i| (i, buffer.get_row_at(i)))
                     .collect::<Vec<_>>()
                     .into_iter();
-                self.view.render_buffer(lines_range, area);
+                self.view.render_buffer(lines_range, area, &render_options);
             }
 
-            let cursor_pos = buffer.get_cursor();
-            self.view
-                .set_cursor(self.margin.0 + cursor_pos.0, cursor_pos.1);
+            let column = cursor_pos.0.max(0) as usize;
+            let (x, y) = if render_options.wrap {
+                (column % width, column / width)
+            } else {
+                (column, 0)
+            };
+            let y = (self.scroll_row..cursor_row).map(&rows).sum::<usize>() + y;
+            self.view.set_cursor(margin + x as i64, y as i64);
 
             let status_text = match &self.mode {
                 Mode::Command => format!(
                     ":{} >> {}c {}r",
                     self.command_buffer, cursor_pos.0, cursor_pos.1
                 ),
+                Mode::View if !self.pending_keys.is_empty() => format!(
+                    "{} {} >> {}c {}r",
+                    self.mode,
+                    format_keys(&self.pending_keys),
+                    cursor_pos.0,
+                    cursor_pos.1
+                ),
                 _ => format!("{} >> {}c {}r", self.mode, cursor_pos.0, cursor_pos.1),
             };
-            self.view
-                .render_status(cursor_pos, h as i64, status_text.as_str());
+            self.view.render_messages(h + 1, &self.messages);
+            self.view.render_status(
+                cursor_pos,
+                h as i64,
+                self.mode.name(),
+                status_text.as_str(),
+                &self.decorations.status_segments(),
+            );
         } else {
             log!("couldn't acquire current_buffer");
         }
@@ -138,122 +361,904 @@ impl App
 
     pub fn wait_for_event(&mut self) -> Result<(), &'static str>
     {
-        match self.events.recv() {
-            Ok(event) => {
-                match event {
-                    Event::Resize => self.render(),
-                    Event::Key(Up) => {
-                        if let Some(buffer) = &mut self.buffer {
-                            buffer.move_cursor(Relative(0, -1));
-                        }
-                    }
-                    Event::Key(Down) => {
-                        if let Some(buffer) = &mut self.buffer {
-                            buffer.move_cursor(Relative(0, 1));
-                        }
+        let event = match self.pending_deadline {
+            Some(deadline) => {
+                let timeout = deadline
+                    .checked_duration_since(Instant::now())
+                    .unwrap_or_else(|| Duration::from_millis(0));
+                match self.events.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.flush_pending_keys();
+                        return Ok(());
                     }
-                    Event::Key(Left) => {
-                        if let Some(buffer) = &mut self.buffer {
-                            buffer.move_cursor(Relative(-1, 0));
-                        }
+                    Err(_) => return Err("error on recv"),
+                }
+            }
+            None => self.events.recv().map_err(|_| "error on recv")?,
+        };
+
+        match event {
+            // the loop in `run` renders after every event
+            Event::Resize => self.view.invalidate(),
+            Event::Key(key) => {
+                let cursor = self.buffer.as_ref().map(Buffer::get_cursor);
+                self.messages.clear();
+                self.handle_key(key);
+                match self.buffer.as_ref().map(Buffer::get_cursor) {
+                    Some(moved) if cursor != Some(moved) => {
+                        self.emit(PluginEvent::CursorMoved(moved))
                     }
-                    Event::Key(Right) => {
-                        if let Some(buffer) = &mut self.buffer {
-                            buffer.move_cursor(Relative(1, 0));
-                        }
+                    _ => {}
+                }
+            }
+            Event::Job { owner, id, output } => {
+                let event = match output {
+                    JobOutput::Stdout(line) => PluginEvent::JobStdout { id, line },
+                    JobOutput::Stderr(line) => PluginEvent::JobStderr { id, line },
+                    JobOutput::Exit(code) => {
+                        self.jobs.finished(&owner, &id);
+                        PluginEvent::JobExit { id, code }
                     }
-                    evt => match &self.mode {
-                        Mode::Command | Mode::View => match evt {
-                            Event::Key(Char(c)) => self.command_push_char(c),
-                            Event::Key(Enter) => self.command_commit(),
-                            Event::Key(Esc) => self.set_mode(Mode::View),
-                            Event::Key(Delete) | Event::Key(Backspace) => {
-                                self.command_buffer.pop();
-                            }
-                            x => log!(format!("{:?}", x)),
-                        },
-                        Mode::Insert => match evt {
-                            Event::Key(Char(c)) => {
-                                if let Some(buffer) = &mut self.buffer {
-                                    buffer.insert(c).unwrap();
-                                }
-                            }
-                            Event::Key(Enter) => {
-                                if let Some(buffer) = &mut self.buffer {
-                                    buffer.insert_newline().unwrap();
-                                }
-                            }
-                            Event::Key(Delete) | Event::Key(Backspace) => {
-                                if let Some(buffer) = &mut self.buffer {
-                                    drop(buffer.remove());
-                                }
-                            }
-                            Event::Key(Esc) => self.set_mode(Mode::View),
-                            x => log!(format!("{:?}", x)),
-                        },
-                        _ => {}
-                    },
+                };
+                self.notify_plugin(&owner, event);
+            }
+            Event::Timer {
+                owner,
+                id,
+                generation,
+            } => {
+                if self.jobs.timer_fired(&owner, &id, generation) {
+                    self.notify_plugin(&owner, PluginEvent::Timer(id));
                 }
-                log!(self.command_buffer);
-                Ok(())
             }
-            _ => Err("error on recv"),
+            // the requests are handled after every event
+            Event::PluginRequest => {}
+            Event::FileChanged(path) => {
+                if self.saved_at.is_none() || modified(&path) != self.saved_at {
+                    self.messages
+                        .push(format!("`{}` changed on disk", path.display()));
+                }
+            }
+            Event::Terminate => self.set_mode(Mode::Exit),
+            Event::Suspend => self.suspend(),
         }
+        log!(self.command_buffer);
+        Ok(())
     }
 
-    pub fn command_commit(&mut self)
+    // keys are collected until they form a binding of the current mode. if
+    // they could still be extended to a longer binding, the decision is
+    // deferred until the next key or `timeoutlen` elapsed
+    fn handle_key(&mut self, key: Key)
     {
-        if self.buffer.is_none() {
+        self.pending_keys.push(key);
+        self.pending_deadline = None;
+
+        let command = match self.keymap.lookup(self.mode.name(), &self.pending_keys) {
+            Lookup::Exact(command) => command.to_string(),
+            Lookup::Prefix | Lookup::Ambiguous => {
+                let timeout = self.option("timeoutlen").as_number().max(0) as u64;
+                self.pending_deadline = Some(Instant::now() + Duration::from_millis(timeout));
+                return;
+            }
+            Lookup::None => {
+                self.flush_pending_keys();
+                return;
+            }
+        };
+
+        self.pending_keys.clear();
+        self.execute(&command);
+    }
+
+    // resolves the pending keys to the longest binding they start with.
+    // keys following that binding are handled again, keys without any
+    // binding fall back to the default behaviour of the mode
+    fn flush_pending_keys(&mut self)
+    {
+        self.pending_deadline = None;
+        let keys = std::mem::take(&mut self.pending_keys);
+        if keys.is_empty() {
             return;
         }
-        if self
-            .command_manager
-            .dispatch(self.buffer.as_mut().unwrap(), &self.command_buffer)
-            .is_ok()
+
+        let mode = self.mode.name();
+        let resolved = (1..=keys.len()).rev().find_map(|len| {
+            self.keymap
+                .get(mode, &keys[..len])
+                .map(|cmd| (len, cmd.clone()))
+        });
+
+        let rest = match resolved {
+            Some((len, command)) => {
+                self.execute(&command);
+                &keys[len..]
+            }
+            None => {
+                self.default_key(keys[0].clone());
+                &keys[1..]
+            }
+        };
+
+        for key in rest {
+            self.handle_key(key.clone());
+        }
+    }
+
+    fn default_key(&mut self, key: Key)
+    {
+        match &self.mode {
+            Mode::Command => match key {
+                Char(c) => self.command_push_char(c),
+                Enter => self.command_commit(),
+                Esc => self.set_mode(Mode::View),
+                Delete | Backspace => {
+                    self.command_buffer.pop();
+                }
+                x => log!(format!("{:?}", x)),
+            },
+            Mode::View => match key {
+                Esc => self.set_mode(Mode::View),
+                x => log!("no binding for {:?}", x),
+            },
+            Mode::Insert => match key {
+                Char(c) => self.edit_buffer(|buffer| buffer.insert(c).unwrap()),
+                Enter => self.edit_buffer(|buffer| buffer.insert_newline().unwrap()),
+                Delete | Backspace => self.edit_buffer(|buffer| {
+                    buffer.remove().ok();
+                }),
+                Esc => self.set_mode(Mode::View),
+                x => log!(format!("{:?}", x)),
+            },
+            Mode::Custom(name) => match key {
+                Esc => self.set_mode(Mode::View),
+                x => log!("no binding for {:?} in mode {}", x, name),
+            },
+            _ => {}
+        }
+    }
+
+    pub fn command_commit(&mut self)
+    {
+        let command = self.command_buffer.clone();
+        self.set_mode(Mode::View);
+        self.execute(&command);
+    }
+
+    pub fn command_push_char(&mut self, c: char)
+    {
+        log!("got {}", c);
+        self.command_buffer.push(c);
+    }
+
+    // runs a plugin command or one of the builtin commands
+    pub fn execute(&mut self, command: &str)
+    {
+        if let Some(buffer) = self.buffer.as_mut() {
+            if let Some(result) = self.command_manager.dispatch(buffer, command) {
+                if let Err(err) = result {
+                    self.messages.push(err);
+                }
+                return;
+            }
+        }
+
+        let mut args = command.split_whitespace();
+        let name = args.next().unwrap_or("");
+        match BUILTIN_COMMANDS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
         {
+            Some((_, run)) => run(self, args.collect()),
+            None => log!(format!("no action for command `{}`", name)),
+        }
+    }
+
+    fn write_buffer(&mut self)
+    {
+        // TODO: take alternative path from w arguments here
+        if let Some(path) = self.buffer.as_ref().and_then(|b| b.source_path().clone()) {
+            self.emit(PluginEvent::BeforeSave(path.clone()));
+            let written = self.buffer.as_ref().unwrap().write(&path);
+            log!(format!("{:?}", written));
+            if written.is_ok() {
+                self.saved_at = modified(&path);
+                self.emit(PluginEvent::AfterSave(path));
+            }
+        }
+    }
+
+    fn mode_command(&mut self, name: Option<&str>)
+    {
+        match name.and_then(|name| self.mode_named(name)) {
+            Some(mode) => self.set_mode(mode),
+            None => self.messages.push("unknown mode".to_string()),
+        }
+    }
+
+    fn show_config(&mut self)
+    {
+        self.messages = self.config.describe();
+        if self.messages.is_empty() {
+            self.messages.push("no configuration loaded".to_string());
+        }
+    }
+
+    fn list_plugins(&mut self)
+    {
+        self.messages = self
+            .command_manager
+            .statuses()
+            .iter()
+            .map(|status| status.to_string())
+            .collect();
+    }
+
+    fn move_cursor(&mut self, mv: CursorMove)
+    {
+        if let Some(buffer) = &mut self.buffer {
+            buffer.move_cursor(mv);
+        }
+    }
+
+    // moves viewport and cursor by the height of the buffer area
+    fn scroll_page(&mut self, direction: i64)
+    {
+        let height = self.view.size().1.saturating_sub(1).max(1);
+        if let Some(buffer) = &mut self.buffer {
+            let last_row = buffer.content_len().saturating_sub(1);
+            self.scroll_row = if direction < 0 {
+                self.scroll_row.saturating_sub(height)
+            } else {
+                (self.scroll_row + height).min(last_row)
+            };
+            buffer.move_cursor(Relative(0, direction * height as i64));
+        }
+    }
+
+    // inserts a tab or, with `expandtab`, spaces up to the next multiple of
+    // `shiftwidth`. a `shiftwidth` of 0 uses the value of `tabstop`
+    fn indent(&mut self)
+    {
+        let expandtab = self.option("expandtab").as_bool();
+        let width = match self.option("shiftwidth").as_number() {
+            0 => self.option("tabstop").as_number(),
+            width => width,
+        }
+        .max(1);
+        self.edit_buffer(|buffer| {
+            if expandtab {
+                let column = buffer.get_cursor().0;
+                for _ in 0..width - column % width {
+                    buffer.insert(' ').unwrap();
+                }
+            } else {
+                buffer.insert('\t').unwrap();
+            }
+        });
+    }
+
+    fn edit_buffer<F>(&mut self, edit: F)
+    where
+        F: FnOnce(&mut Buffer),
+    {
+        if let Some(buffer) = &mut self.buffer {
+            let (len, row) = (buffer.content_len(), buffer.get_cursor().1);
+            edit(buffer);
+            // lines are inserted or joined below the line of the cursor
+            let row = row.min(buffer.get_cursor().1).max(0) as usize + 1;
+            let new_len = buffer.content_len();
+            if new_len > len {
+                self.decorations.lines_inserted(row, new_len - len);
+            } else if new_len < len {
+                self.decorations.lines_removed(row, len - new_len);
+            }
+            self.emit(PluginEvent::TextChanged);
+        }
+    }
+
+    fn emit(&mut self, event: PluginEvent)
+    {
+        if let Err(err) = self.command_manager.emit(self.buffer.as_mut(), &event) {
+            log!("error on event {:?}: {}", event.kind(), err);
+        }
+    }
+
+    fn notify_plugin(&mut self, name: &str, event: PluginEvent)
+    {
+        let notified = self
+            .command_manager
+            .notify_plugin(name, self.buffer.as_mut(), &event);
+        if let Err(err) = notified {
+            log!("error on event {:?}: {}", event.kind(), err);
+        }
+    }
+
+    // completes the command name on the command line if it is unambiguous,
+    // lists the candidates otherwise
+    fn complete_command(&mut self)
+    {
+        if self.command_buffer.contains(' ') {
+            return;
+        }
+        let mut candidates = BUILTIN_COMMANDS
+            .iter()
+            .map(|(cmd, _)| cmd.to_string())
+            .chain(self.command_manager.commands())
+            .filter(|cmd| cmd.starts_with(&self.command_buffer))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        candidates.dedup();
+
+        match candidates.len() {
+            0 => {}
+            1 => self.command_buffer = candidates.remove(0),
+            _ => self.messages = vec![candidates.join(" ")],
+        }
+    }
+
+    // `map [mode] <keys> <command>`, mode defaults to `view`
+    fn map_keys(&mut self, args: Vec<&str>)
+    {
+        let (mode, args) = split_mode_argument(&args, &self.plugin_modes);
+        match args.split_first() {
+            None => self.messages = self.keymap.list(mode),
+            Some((_, [])) => self
+                .messages
+                .push("usage: map [mode] <keys> <command>".to_string()),
+            Some((keys, command)) => match parse_keys(keys) {
+                Ok(keys) => self.keymap.map(mode, keys, command.join(" ")),
+                Err(err) => self.messages.push(err),
+            },
+        }
+    }
+
+    // `unmap [mode] <keys>`, mode defaults to `view`
+    fn unmap_keys(&mut self, args: Vec<&str>)
+    {
+        let (mode, args) = split_mode_argument(&args, &self.plugin_modes);
+        match args {
+            [keys] => match parse_keys(keys) {
+                Ok(keys) => {
+                    if !self.keymap.unmap(mode, &keys) {
+                        self.messages
+                            .push(format!("no mapping for `{}`", format_keys(&keys)));
+                    }
+                }
+                Err(err) => self.messages.push(err),
+            },
+            _ => self.messages.push("usage: unmap [mode] <keys>".to_string()),
+        }
+    }
+
+    // loads a dynamic library, wasm or script plugin and registers its
+    // commands. disabled plugins are skipped without an error
+    fn load_plugin(&mut self, path: &Path) -> Result<(), String>
+    {
+        let source = path.display().to_string();
+        let file_name = name_from_path(path);
+        if !self.config.plugins.is_enabled(&[&file_name]) {
+            log!("skipping disabled plugin {}", source);
+            return Ok(());
+        }
+        let loaded = if WasmPlugin::is_module(path) {
+            WasmPlugin::load(path).map(|plugin| plugin as Box<dyn Plugin>)
+        } else if ScriptPlugin::is_script(path) {
+            ScriptPlugin::load(path).map(|plugin| plugin as Box<dyn Plugin>)
         } else {
-            match self.command_buffer.as_ref() {
-                "q" => {
-                    self.set_mode(Mode::Exit);
-                    return;
+            DynamicPlugin::load(path).map(|plugin| plugin as Box<dyn Plugin>)
+        };
+        self.install_plugin(loaded, &source, &file_name)
+    }
+
+    // starts a plugin process listed in `[[plugins.process]]`
+    fn spawn_plugin(&mut self, process: &ProcessConfig) -> Result<(), String>
+    {
+        if !self.config.plugins.is_enabled(&[&process.name]) {
+            log!("skipping disabled plugin {}", process.name);
+            return Ok(());
+        }
+        let source = format!("{} {}", process.command, process.args.join(" "));
+        let loaded = ProcessPlugin::spawn(process, self.event_loop.sender())
+            .map(|plugin| plugin as Box<dyn Plugin>);
+        self.install_plugin(loaded, source.trim(), &process.name)
+    }
+
+    // configures a freshly loaded plugin and registers its commands. `alias`
+    // is the name the plugin is known by before it is loaded. the error is the
+    // message to show
+    fn install_plugin(
+        &mut self,
+        loaded: PluginResult<Box<dyn Plugin>>,
+        source: &str,
+        alias: &str,
+    ) -> Result<(), String>
+    {
+        match loaded {
+            Ok(mut plugin) => {
+                let name = plugin.name().to_string();
+                // the plugin is only known by `alias` until it is loaded, so
+                // the name it gives itself can only be checked now
+                if self.config.plugins.is_disabled(&[&name]) {
+                    log!("skipping disabled plugin {}", name);
+                    return Ok(());
                 }
-                "w" => {
-                    if let Some(buffer) = &self.buffer {
-                        // TODO: take alternative path from w arguments here
-                        let path = buffer.source_path().clone().unwrap();
-                        log!(format!("{:?}", buffer.write(&path)));
+                let config = self
+                    .config
+                    .plugin
+                    .get(&name)
+                    .or_else(|| self.config.plugin.get(alias))
+                    .cloned()
+                    .unwrap_or_default();
+                if let Err(err) = plugin.configure(&config) {
+                    log!("configuring plugin {}: {}", name, err);
+                    let message = format!("plugin `{}` failed: {}", name, err);
+                    self.command_manager.fail(plugin.manifest(), source, err);
+                    return Err(message);
+                }
+                let commands = plugin.commands();
+                let modes = plugin.modes();
+                let bindings = plugin.bindings();
+                match self.command_manager.add_plugin(plugin, source) {
+                    Ok(()) => {
+                        log!("loading plugin {}: okay", name);
+                        self.map_plugin_commands(commands);
+                        self.define_plugin_modes(&name, modes);
+                        self.map_plugin_bindings(bindings);
+                        Ok(())
+                    }
+                    Err(err) => {
+                        log!("loading plugin {}: {}", name, err);
+                        Err(format!("plugin `{}` failed: {}", name, err))
                     }
                 }
-                cmd => log!(format!("no action for command `{}`", cmd)),
+            }
+            Err(err) => {
+                log!("rejecting plugin {}: {}", source, err);
+                let message = format!("plugin `{}` rejected: {}", source, err);
+                self.command_manager.reject(source, err);
+                Err(message)
             }
         }
-        self.set_mode(Mode::View);
     }
 
-    pub fn command_push_char(&mut self, c: char)
+    fn handle_plugin_requests(&mut self)
     {
-        log!("got {}", c);
-        match c {
-            ':' => self.set_mode(Mode::Command),
-            'i' => self.set_mode(Mode::Insert),
-            _ => {
-                self.command_buffer.push(c);
-                if self.buffer.is_some()
-                    && self
-                        .command_manager
-                        .dispatch(self.buffer.as_mut().unwrap(), &self.command_buffer)
-                        .is_ok()
-                {
-                    self.command_buffer.clear();
+        for (detached, reason) in self.command_manager.take_disabled() {
+            self.messages.push(format!(
+                "plugin `{}` was disabled, it {}",
+                detached.name, reason
+            ));
+            self.forget_plugin(detached);
+        }
+        for (owner, request) in self.command_manager.requests() {
+            match request {
+                PluginRequest::Message(message) => self.messages.push(message),
+                PluginRequest::SetMode(name) => match self.mode_named(&name) {
+                    Some(mode) => self.set_mode(mode),
+                    None => self.messages.push(format!("unknown mode `{}`", name)),
+                },
+                PluginRequest::Map {
+                    mode,
+                    keys,
+                    command,
+                } => match parse_keys(&keys) {
+                    Ok(keys) => self.keymap.map(&mode, keys, command),
+                    Err(err) => self.messages.push(err),
+                },
+                PluginRequest::SetStatus { id, text } => {
+                    self.decorations.set_status(&owner, id, text)
+                }
+                PluginRequest::AddSign { row, text } => {
+                    self.decorations.add_sign(&owner, row, text)
+                }
+                PluginRequest::AddVirtualText { row, text } => {
+                    self.decorations.add_virtual_text(&owner, row, text)
+                }
+                PluginRequest::AddHighlight {
+                    row,
+                    start,
+                    end,
+                    group,
+                } => self
+                    .decorations
+                    .add_highlight(&owner, row, Highlight { start, end, group }),
+                PluginRequest::ClearDecorations => self.decorations.clear(&owner),
+                PluginRequest::StartJob { id, command, args } => {
+                    let started = valid_job_id(&id)
+                        .and_then(|_| self.jobs.start(&owner, id, &command, &args));
+                    if let Err(err) = started {
+                        self.messages.push(format!("plugin `{}`: {}", owner, err));
+                    }
+                }
+                PluginRequest::StopJob(id) => {
+                    self.jobs.stop(&owner, &id);
+                }
+                PluginRequest::StartTimer {
+                    id,
+                    interval,
+                    repeat,
+                } => match valid_job_id(&id) {
+                    Ok(()) => {
+                        let interval = Duration::from_millis(interval).max(MIN_TIMER_INTERVAL);
+                        self.jobs.start_timer(&owner, id, interval, repeat)
+                    }
+                    Err(err) => self.messages.push(format!("plugin `{}`: {}", owner, err)),
+                },
+                PluginRequest::StopTimer(id) => {
+                    self.jobs.stop_timer(&owner, &id);
+                }
+            }
+        }
+    }
+
+    // unloads the plugin and drops its decorations, modes and default
+    // bindings, returns the path it was loaded from
+    fn unload_plugin(&mut self, name: &str) -> Option<String>
+    {
+        match self.command_manager.remove_plugin(name) {
+            Ok(detached) => {
+                let source = detached.source.clone();
+                self.forget_plugin(detached);
+                Some(source)
+            }
+            Err(err) => {
+                self.messages.push(err);
+                None
+            }
+        }
+    }
+
+    // drops what the editor keeps of a plugin that is gone
+    fn forget_plugin(&mut self, detached: Detached)
+    {
+        let name = detached.name;
+        self.decorations.clear(&name);
+        self.jobs.stop_all(&name);
+        let bindings = detached
+            .commands
+            .into_iter()
+            .map(|command| KeyBinding {
+                mode: "view".to_string(),
+                keys: command.clone(),
+                command,
+            })
+            .chain(detached.bindings);
+        for binding in bindings {
+            if let Ok(keys) = parse_keys(&binding.keys) {
+                if self.keymap.get(&binding.mode, &keys) == Some(&binding.command) {
+                    self.keymap.unmap(&binding.mode, &keys);
+                }
+            }
+        }
+        let owned = self
+            .plugin_modes
+            .iter()
+            .filter(|(_, owner)| **owner == name)
+            .map(|(mode, _)| mode.clone())
+            .collect::<Vec<_>>();
+        for mode in owned {
+            self.plugin_modes.remove(&mode);
+            if self.mode.name() == mode {
+                self.set_mode(Mode::View);
+            }
+        }
+    }
+
+    // plugin commands are reachable through the view keymap unless the user
+    // bound the same keys to something else
+    fn map_plugin_commands(&mut self, commands: Vec<String>)
+    {
+        for command in commands {
+            if let Ok(keys) = parse_keys(&command) {
+                self.keymap.map_default("view", keys, command);
+            }
+        }
+    }
+
+    // the first plugin to define a mode owns it, the built-in modes cannot
+    // be redefined
+    fn define_plugin_modes(&mut self, owner: &str, modes: Vec<String>)
+    {
+        for mode in modes {
+            if Mode::from_name(&mode).is_some() || mode == Mode::Exit.name() {
+                self.messages.push(format!(
+                    "plugin `{}` cannot redefine mode `{}`",
+                    owner, mode
+                ));
+            } else if !self.plugin_modes.contains_key(&mode) {
+                self.keymap.add_mode(&mode);
+                self.plugin_modes.insert(mode, owner.to_string());
+            }
+        }
+    }
+
+    fn map_plugin_bindings(&mut self, bindings: Vec<KeyBinding>)
+    {
+        for binding in bindings {
+            match parse_keys(&binding.keys) {
+                Ok(keys) => self
+                    .keymap
+                    .map_default(&binding.mode, keys, binding.command),
+                Err(err) => self.messages.push(err),
+            }
+        }
+    }
+
+    // resolves built-in modes and those defined by plugins
+    fn mode_named(&self, name: &str) -> Option<Mode>
+    {
+        Mode::from_name(name).or_else(|| {
+            if self.plugin_modes.contains_key(name) {
+                Some(Mode::Custom(name.to_string()))
+            } else {
+                None
+            }
+        })
+    }
+
+    // `colorscheme <name>` switches the theme, without a name the available
+    // themes are listed
+    fn colorscheme(&mut self, name: Option<&str>)
+    {
+        match name {
+            Some(name) => match Theme::load(name) {
+                Ok(theme) => self.view.set_theme(theme),
+                Err(err) => self.messages.push(err),
+            },
+            None => {
+                let current = self.view.theme().name().to_string();
+                self.messages = Theme::available()
+                    .into_iter()
+                    .map(|name| {
+                        if name == current {
+                            format!("{} (current)", name)
+                        } else {
+                            name
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    // `plugin unload <name>` and `plugin reload <name>`
+    fn plugin_command(&mut self, args: Vec<&str>)
+    {
+        match args.as_slice() {
+            ["unload", name] => {
+                if self.unload_plugin(name).is_some() {
+                    self.messages.push(format!("plugin `{}` unloaded", name));
+                }
+            }
+            ["reload", name] => {
+                if let Some(source) = self.unload_plugin(name) {
+                    let process = self
+                        .config
+                        .plugins
+                        .process
+                        .iter()
+                        .find(|process| process.name == *name)
+                        .cloned();
+                    let loaded = match process {
+                        Some(process) => self.spawn_plugin(&process),
+                        None => self.load_plugin(Path::new(&source)),
+                    };
+                    match loaded {
+                        Ok(()) => self.messages.push(format!("plugin `{}` reloaded", name)),
+                        Err(err) => self.messages.push(err),
+                    }
                 }
             }
+            _ => self
+                .messages
+                .push("usage: plugin unload|reload <name>".to_string()),
         }
     }
 
+    // buffer-local values take precedence over the global ones
+    pub fn option(&self, name: &str) -> OptionValue
+    {
+        self.buffer
+            .as_ref()
+            .and_then(|buffer| buffer.options().get(name))
+            .or_else(|| self.options.get(name))
+            .cloned()
+            .unwrap_or(OptionValue::Bool(false))
+    }
+
+    fn set_options(&mut self, args: Vec<&str>, local: bool)
+    {
+        if args.is_empty() {
+            self.messages = OptionSpec::all()
+                .iter()
+                .map(|spec| format!("{}={}", spec.name, self.option(spec.name)))
+                .collect();
+            return;
+        }
+
+        for arg in args {
+            match parse_set_argument(arg) {
+                Ok(SetAction::Show(spec)) => {
+                    let value = self.option(spec.name);
+                    self.messages.push(format!("{}={}", spec.name, value));
+                }
+                Ok(SetAction::Toggle(spec)) => {
+                    let value = !self.option(spec.name).as_bool();
+                    self.assign_option(spec, OptionValue::Bool(value), local);
+                }
+                Ok(SetAction::Assign(spec, value)) => self.assign_option(spec, value, local),
+                Err(err) => self.messages.push(err),
+            }
+        }
+    }
+
+    fn assign_option(&mut self, spec: &'static OptionSpec, value: OptionValue, local: bool)
+    {
+        match (spec.scope, self.buffer.as_mut()) {
+            (OptionScope::Buffer, Some(buffer)) => {
+                buffer.options_mut().set(spec, value.clone());
+                if !local {
+                    self.options.set(spec, value);
+                }
+            }
+            (OptionScope::Global, _) if local => {
+                self.messages
+                    .push(format!("`{}` is a global option", spec.name));
+            }
+            (OptionScope::Buffer, None) if local => {
+                self.messages
+                    .push("no buffer to set options for".to_string());
+            }
+            _ => self.options.set(spec, value),
+        }
+    }
+
+    // stops the process like the shell would without a handler for SIGTSTP,
+    // the terminal is taken back once it continues
+    fn suspend(&mut self)
+    {
+        self.view.suspend();
+        emulate_default_handler(SIGTSTP).ok();
+        self.view.resume();
+    }
+
     fn set_mode(&mut self, mode: Mode)
     {
         log!("new mode {}", mode);
+        let changed = self.mode.name() != mode.name();
         self.mode = mode;
         self.command_buffer.clear();
+        if changed {
+            self.emit(PluginEvent::ModeChanged(self.mode.name().to_string()));
+        }
+    }
+}
+
+impl Drop for App
+{
+    fn drop(&mut self)
+    {
+        self.event_loop.stop();
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime>
+{
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// ids are sent to plugins in front of the event data, separated by a space
+fn valid_job_id(id: &str) -> Result<(), String>
+{
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        Err(format!("invalid job id `{}`", id))
+    } else {
+        Ok(())
+    }
+}
+
+fn split_mode_argument<'a, 'b>(
+    args: &'b [&'a str],
+    plugin_modes: &HashMap<String, String>,
+) -> (&'a str, &'b [&'a str])
+{
+    match args.split_first() {
+        Some((mode, rest))
+            if Mode::from_name(mode).is_some() || plugin_modes.contains_key(*mode) =>
+        {
+            (mode, rest)
+        }
+        _ => ("view", args),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::terminal::headless::HeadlessTerminal;
+
+    #[test]
+    fn edits_and_writes_a_file()
+    {
+        let path = std::env::temp_dir().join(format!("loe-app-{}", std::process::id()));
+        std::fs::write(&path, "world\n").unwrap();
+
+        let terminal = Arc::new(HeadlessTerminal::new(40, 10));
+        let view = View::with_terminal(terminal.clone());
+        let mut app = App::new(Config::default(), view).open(path.to_str().unwrap());
+        let keys = "ihello<Esc>:w<CR>";
+        terminal.push_keys(keys).unwrap();
+        for _ in parse_keys(keys).unwrap() {
+            app.wait_for_event().unwrap();
+            app.handle_plugin_requests();
+        }
+        app.render();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(written.starts_with("helloworld"));
+        assert!(terminal.screen()[0].ends_with("helloworld"));
+        assert_eq!(terminal.cursor(), (app.margin.0 + 5, 0));
+        assert_eq!(app.mode.name(), "view");
+
+        // writing the file is reported by the watcher as well
+        terminal.push_event(Event::Terminate);
+        for _ in 0..10 {
+            if app.mode.name() != Mode::Exit.name() {
+                app.wait_for_event().unwrap();
+            }
+        }
+        assert_eq!(app.mode.name(), Mode::Exit.name());
+    }
+
+    #[test]
+    fn shows_messages_plugins_send_on_their_own()
+    {
+        // answers `initialize` and `configure`, then speaks up a while later
+        let script = r#"
+            read line
+            echo '{"jsonrpc": "2.0", "id": 1, "result": {"protocol_version": 1}}'
+            read line
+            echo '{"jsonrpc": "2.0", "id": 2, "result": null}'
+            sleep 0.2
+            echo '{"jsonrpc": "2.0", "method": "message", "params": {"text": "lint done"}}'
+            cat > /dev/null
+        "#;
+        let mut config = Config::default();
+        config.plugins.process.push(ProcessConfig {
+            name: "lint".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            timeout: 1000,
+        });
+        let path = std::env::temp_dir().join(format!("loe-notify-{}", std::process::id()));
+        std::fs::write(&path, "text\n").unwrap();
+
+        let terminal = Arc::new(HeadlessTerminal::new(40, 10));
+        let view = View::with_terminal(terminal.clone());
+        let mut app = App::new(config, view).open(path.to_str().unwrap());
+        assert!(app.messages.is_empty());
+
+        // no key is pressed, the notification wakes up the app by itself
+        let shown = |app: &App| app.messages.iter().any(|message| message == "lint done");
+        for _ in 0..10 {
+            if !shown(&app) {
+                app.wait_for_event().unwrap();
+                app.handle_plugin_requests();
+            }
+        }
+        app.render();
+        std::fs::remove_file(&path).ok();
+        assert!(terminal
+            .screen()
+            .iter()
+            .any(|row| row.contains("lint done")));
     }
 }
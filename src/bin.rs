@@ -1,10 +1,14 @@
 #![feature(result_map_or_else)]
 #![allow(clippy::string_lit_as_bytes)]
 
+extern crate crossterm;
 extern crate lazy_static;
+extern crate libc;
 extern crate libloading;
+extern crate loe_plugin;
 extern crate rustbox;
 extern crate serde_derive;
+extern crate signal_hook;
 extern crate toml;
 
 #[macro_use]
@@ -12,28 +16,52 @@ mod macros;
 mod app;
 mod buffer;
 mod config;
+mod decoration;
+mod event_loop;
 mod input;
+mod job;
+mod keymap;
 mod mode;
+mod options;
+mod panic;
 mod plugin;
 mod terminal;
+mod theme;
 mod view;
 
+use std::panic::AssertUnwindSafe;
+
 use self::app::App;
 use self::config::Config;
-
-const CONFIG_PATH: &str = "~/.config/loe/config";
+use self::view::View;
 
 fn main()
 {
-    // TODO: try reading this from program arguments
-    let config_path = CONFIG_PATH;
-    let config = Config::from_path(config_path).unwrap_or_else(|err| {
-        log!("could not load configuration from `{}`", config_path);
+    panic::install_hook();
+
+    // TODO: try reading the layers from program arguments
+    let config = Config::load().unwrap_or_else(|err| {
+        log!("could not load configuration");
         log!("got error: {:?}", err);
         Config::default()
     });
 
     log!("{:?}", config);
 
-    App::new(config).with_args(std::env::args()).run().unwrap();
+    // the app restores the terminal when it is dropped, so a panic is only
+    // reported after unwinding out of it
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let view = View::new(config.terminal);
+        App::new(config, view)
+            .with_environment()
+            .with_args(std::env::args())
+            .run()
+    }));
+    match result {
+        Ok(result) => result.unwrap(),
+        Err(err) => {
+            eprintln!("loe crashed: {}", panic::message(&*err));
+            std::process::exit(101);
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use crate::input::CursorMove;
+use crate::options::Options;
 use crate::terminal::Position;
 
 use ::libloe::buffer as libloe;
@@ -8,6 +9,15 @@ use ::libloe::buffer as libloe;
 pub struct Buffer
 {
     lbuffer: libloe::Buffer,
+    options: Options,
+}
+
+// content and cursor of a buffer, used to roll back edits that failed
+// halfway through
+pub struct Snapshot
+{
+    lines: Vec<String>,
+    cursor: Position,
 }
 
 impl Buffer
@@ -16,6 +26,7 @@ impl Buffer
     {
         Ok(Self {
             lbuffer: libloe::create(path)?,
+            options: Options::new(),
         })
     }
 
@@ -23,6 +34,7 @@ impl Buffer
     {
         Ok(Self {
             lbuffer: libloe::load(path)?,
+            options: Options::new(),
         })
     }
 
@@ -41,9 +53,68 @@ impl Buffer
         self.lbuffer.content.len()
     }
 
-    pub fn inner_mut(&mut self) -> &mut libloe::Buffer
+    pub fn line(&self, row: usize) -> Option<&str>
+    {
+        self.lbuffer.content.get(row).map(String::as_str)
+    }
+
+    pub fn lines(&self) -> &[String]
+    {
+        &self.lbuffer.content
+    }
+
+    // replaces the whole content, a buffer always keeps at least one line
+    pub fn set_lines(&mut self, mut lines: Vec<String>)
     {
-        &mut self.lbuffer
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        self.lbuffer.content = lines;
+        self.clamp_cursor();
+    }
+
+    pub fn set_line(&mut self, row: usize, line: String) -> bool
+    {
+        match self.lbuffer.content.get_mut(row) {
+            Some(current) => {
+                *current = line;
+                self.clamp_cursor();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert_line(&mut self, row: usize, line: String) -> bool
+    {
+        if self.lbuffer.content.len() < row {
+            return false;
+        }
+        self.lbuffer.content.insert(row, line);
+        true
+    }
+
+    pub fn remove_line(&mut self, row: usize) -> bool
+    {
+        if self.lbuffer.content.len() <= row {
+            return false;
+        }
+        self.lbuffer.content.remove(row);
+        if self.lbuffer.content.is_empty() {
+            self.lbuffer.content.push(String::new());
+        }
+        self.clamp_cursor();
+        true
+    }
+
+    pub fn options(&self) -> &Options
+    {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options
+    {
+        &mut self.options
     }
 
     pub fn insert(&mut self, c: char) -> Result<(), &'static str>
@@ -75,4 +146,33 @@ impl Buffer
     {
         libloe::move_cursor(&mut self.lbuffer, mv)
     }
+
+    // moves the cursor back into the content after lines were replaced or
+    // removed underneath it, or it was placed by a plugin
+    pub fn clamp_cursor(&mut self)
+    {
+        let (col, row) = self.lbuffer.cursor;
+        let last_row = self.lbuffer.content.len().saturating_sub(1) as i64;
+        let row = row.max(0).min(last_row);
+        let len = self
+            .lbuffer
+            .content
+            .get(row as usize)
+            .map_or(0, |line| line.chars().count()) as i64;
+        self.lbuffer.cursor = (col.max(0).min(len), row);
+    }
+
+    pub fn snapshot(&self) -> Snapshot
+    {
+        Snapshot {
+            lines: self.lbuffer.content.clone(),
+            cursor: self.lbuffer.cursor,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot)
+    {
+        self.lbuffer.content = snapshot.lines;
+        self.lbuffer.cursor = snapshot.cursor;
+    }
 }
@@ -1,6 +1,17 @@
+use std::collections::{BTreeMap, HashMap};
 use std::convert::From;
+use std::path::PathBuf;
 
 use serde_derive::Deserialize;
+use toml::value::{Table, Value};
+
+use crate::terminal::Backend;
+
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/loe/config";
+pub const USER_CONFIG_PATH: &str = "~/.config/loe/config";
+pub const PROJECT_CONFIG_PATH: &str = ".loe/config";
+pub const INIT_SCRIPT_PATH: &str = "~/.config/loe/init.rhai";
+pub const THEME_DIR_PATH: &str = "~/.config/loe/themes";
 
 #[derive(Debug)]
 pub enum ConfigError
@@ -11,28 +22,306 @@ pub enum ConfigError
 
 pub type ConfigResult = Result<Config, ConfigError>;
 
+// layers are listed from lowest to highest precedence
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ConfigSource
+{
+    System,
+    User,
+    Project,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigOrigin
+{
+    pub source: ConfigSource,
+    pub path: PathBuf,
+}
+
+// an executable speaking the plugin protocol over stdin and stdout
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProcessConfig
+{
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // milliseconds to wait for an answer of the plugin
+    #[serde(default = "default_process_timeout")]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PluginsConfig
+{
+    // plugins listed earlier win when two plugins provide the same command
+    #[serde(default)]
+    pub priority: Vec<String>,
+    // built-in commands plugins are allowed to replace
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<String>,
+    // if not empty, only these plugins are loaded
+    #[serde(default)]
+    pub enabled: Vec<String>,
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    #[serde(default)]
+    pub process: Vec<ProcessConfig>,
+}
+
 #[derive(Debug, Default, Deserialize)]
 pub struct Config
 {
     #[serde(rename = "plugin-path")]
     pub plugin_path: Option<String>,
+    // `rustbox` or `crossterm`
+    #[serde(default)]
+    pub terminal: Backend,
+    // name of the color theme loaded on startup
+    pub colorscheme: Option<String>,
+    #[serde(default)]
+    pub options: Table,
+    #[serde(default)]
+    pub keymap: HashMap<String, HashMap<String, String>>,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    // `[plugin.<name>]` tables handed to the plugins on load
+    #[serde(default)]
+    pub plugin: HashMap<String, Table>,
+    #[serde(skip)]
+    values: Table,
+    // keyed by the path of table names leading to each value
+    #[serde(skip)]
+    origins: BTreeMap<Vec<String>, ConfigOrigin>,
+    // layers that were skipped and why
+    #[serde(skip)]
+    pub errors: Vec<String>,
 }
 
 impl Config
 {
-    pub fn from_path(path: &str) -> ConfigResult
+    pub fn layers() -> Vec<(ConfigSource, &'static str)>
+    {
+        vec![
+            (ConfigSource::System, SYSTEM_CONFIG_PATH),
+            (ConfigSource::User, USER_CONFIG_PATH),
+            (ConfigSource::Project, PROJECT_CONFIG_PATH),
+        ]
+    }
+
+    pub fn load() -> ConfigResult
+    {
+        Self::from_layers(&Self::layers())
+    }
+
+    // a layer that cannot be read or holds a value of the wrong type is
+    // skipped as a whole, the other layers are still merged
+    pub fn from_layers(layers: &[(ConfigSource, &str)]) -> ConfigResult
+    {
+        let mut values = Table::new();
+        let mut origins = BTreeMap::new();
+        let mut errors = vec![];
+
+        for (source, path) in layers {
+            let path = expand_path(path);
+            if !path.exists() {
+                continue;
+            }
+            let origin = ConfigOrigin {
+                source: *source,
+                path: path.clone(),
+            };
+            let merged = read_table(&path).and_then(|table| {
+                let mut merged = (values.clone(), origins.clone());
+                merge_table(&mut merged.0, table, &[], &origin, &mut merged.1);
+                Value::Table(merged.0.clone()).try_into::<Config>()?;
+                Ok(merged)
+            });
+            match merged {
+                Ok(merged) => {
+                    values = merged.0;
+                    origins = merged.1;
+                    log!("loaded {} configuration from `{}`", source, path.display());
+                }
+                Err(err) => {
+                    log!("skipping configuration `{}`: {:?}", path.display(), err);
+                    errors.push(format!(
+                        "skipped {} configuration `{}`: {}",
+                        source,
+                        path.display(),
+                        err
+                    ));
+                }
+            }
+        }
+
+        let mut config: Config = Value::Table(values.clone()).try_into()?;
+        config.values = values;
+        config.origins = origins;
+        config.errors = errors;
+        Ok(config)
+    }
+
+    // effective configuration as `key = value` lines, annotated with the
+    // file each setting was taken from
+    pub fn describe(&self) -> Vec<String>
+    {
+        self.origins
+            .iter()
+            .filter_map(|(path, origin)| {
+                lookup(&self.values, path).map(|value| {
+                    format!(
+                        "{} = {}  # {} ({})",
+                        dotted_key(path),
+                        value,
+                        origin.source,
+                        origin.path.display()
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl PluginsConfig
+{
+    // plugins are identified by their file name without `lib` and extension,
+    // or the name of their process, so they are skipped before anything is
+    // loaded. `disabled` also matches the name a plugin gives itself
+    pub fn is_enabled(&self, names: &[&str]) -> bool
+    {
+        (self.enabled.is_empty() || listed(&self.enabled, names)) && !self.is_disabled(names)
+    }
+
+    pub fn is_disabled(&self, names: &[&str]) -> bool
+    {
+        listed(&self.disabled, names)
+    }
+}
+
+impl std::fmt::Display for ConfigSource
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error>
+    {
+        match self {
+            ConfigSource::System => write!(f, "system"),
+            ConfigSource::User => write!(f, "user"),
+            ConfigSource::Project => write!(f, "project"),
+        }
+    }
+}
+
+pub fn expand_path(path: &str) -> PathBuf
+{
+    let mut path_buf = PathBuf::new();
+    // TODO: search for more generic solution
+    if path.starts_with('~') {
+        path_buf.push(dirs::home_dir().unwrap());
+        path_buf.push(&path[2..]);
+    } else {
+        path_buf.push(path);
+    }
+    path_buf
+}
+
+fn read_table(path: &PathBuf) -> Result<Table, ConfigError>
+{
+    let path = std::fs::canonicalize(path)?;
+    let content = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&content)?)
+}
+
+// tables are merged recursively, every other value of `other` replaces the
+// existing one and records `origin` for its full key
+fn merge_table(
+    into: &mut Table,
+    other: Table,
+    prefix: &[String],
+    origin: &ConfigOrigin,
+    origins: &mut BTreeMap<Vec<String>, ConfigOrigin>,
+)
+{
+    for (key, value) in other {
+        let mut full_key = prefix.to_vec();
+        full_key.push(key.clone());
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge_table(existing, table, &full_key, origin, origins);
+            }
+            (_, value) => {
+                forget_origins(origins, &full_key);
+                let value = match value {
+                    Value::Table(table) => {
+                        let mut fresh = Table::new();
+                        merge_table(&mut fresh, table, &full_key, origin, origins);
+                        Value::Table(fresh)
+                    }
+                    value => {
+                        origins.insert(full_key, origin.clone());
+                        value
+                    }
+                };
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+fn default_process_timeout() -> u64
+{
+    1000
+}
+
+fn listed(list: &[String], names: &[&str]) -> bool
+{
+    names
+        .iter()
+        .any(|name| list.iter().any(|entry| entry == name))
+}
+
+// forgets the origins of `path` and everything below it
+fn forget_origins(origins: &mut BTreeMap<Vec<String>, ConfigOrigin>, path: &[String])
+{
+    origins.retain(|k, _| !k.starts_with(path));
+}
+
+fn lookup<'t>(table: &'t Table, path: &[String]) -> Option<&'t Value>
+{
+    let (first, rest) = path.split_first()?;
+    let mut current = table.get(first)?;
+    for part in rest {
+        current = current.as_table()?.get(part)?;
+    }
+    Some(current)
+}
+
+// the key as written in toml, parts that are not bare keys are quoted
+fn dotted_key(path: &[String]) -> String
+{
+    path.iter()
+        .map(|part| {
+            let bare = !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if bare {
+                part.clone()
+            } else {
+                Value::String(part.clone()).to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl std::fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error>
     {
-        let mut path_buf = std::path::PathBuf::new();
-        // TODO: search for more generic solution
-        if path.starts_with('~') {
-            path_buf.push(dirs::home_dir().unwrap());
-            path_buf.push(&path[2..]);
-        } else {
-            path_buf.push(path);
+        match self {
+            ConfigError::File(err) => write!(f, "{}", err),
+            ConfigError::Parse(err) => write!(f, "{}", err),
         }
-        let path = std::fs::canonicalize(path_buf)?;
-        let content = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
     }
 }
 
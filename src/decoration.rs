@@ -0,0 +1,153 @@
+// things plugins show in the view without editing the buffer: status line
+// segments, gutter signs, virtual text after a line and highlighted ranges.
+// every decoration belongs to the plugin that added it, so plugins can clear
+// their own decorations without touching those of others
+
+use std::collections::BTreeMap;
+
+// columns reserved for signs in front of the line numbers
+pub const SIGN_WIDTH: usize = 2;
+
+#[derive(Clone, Debug)]
+pub struct Highlight
+{
+    pub start: usize,
+    pub end: usize,
+    pub group: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Decorations
+{
+    status: BTreeMap<(String, String), String>,
+    signs: BTreeMap<usize, Vec<(String, String)>>,
+    virtual_text: BTreeMap<usize, Vec<(String, String)>>,
+    highlights: BTreeMap<usize, Vec<(String, Highlight)>>,
+}
+
+impl Decorations
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn set_status(&mut self, owner: &str, id: String, text: String)
+    {
+        let key = (owner.to_string(), id);
+        if text.is_empty() {
+            self.status.remove(&key);
+        } else {
+            self.status.insert(key, text);
+        }
+    }
+
+    // a plugin has at most one sign per line
+    pub fn add_sign(&mut self, owner: &str, row: usize, text: String)
+    {
+        let signs = self.signs.entry(row).or_default();
+        signs.retain(|(o, _)| o != owner);
+        signs.push((owner.to_string(), text));
+    }
+
+    pub fn add_virtual_text(&mut self, owner: &str, row: usize, text: String)
+    {
+        self.virtual_text
+            .entry(row)
+            .or_default()
+            .push((owner.to_string(), text));
+    }
+
+    pub fn add_highlight(&mut self, owner: &str, row: usize, highlight: Highlight)
+    {
+        self.highlights
+            .entry(row)
+            .or_default()
+            .push((owner.to_string(), highlight));
+    }
+
+    pub fn clear(&mut self, owner: &str)
+    {
+        self.status.retain(|(o, _), _| o != owner);
+        retain_others(&mut self.signs, owner);
+        retain_others(&mut self.virtual_text, owner);
+        retain_others(&mut self.highlights, owner);
+    }
+
+    // decorations below `row` move down with the lines inserted in front of
+    // them
+    pub fn lines_inserted(&mut self, row: usize, count: usize)
+    {
+        shift_rows(&mut self.signs, row, |r| Some(r + count));
+        shift_rows(&mut self.virtual_text, row, |r| Some(r + count));
+        shift_rows(&mut self.highlights, row, |r| Some(r + count));
+    }
+
+    // decorations of the removed lines are dropped, those below move up
+    pub fn lines_removed(&mut self, row: usize, count: usize)
+    {
+        let moved = |r: usize| r.checked_sub(count).filter(|r| *r >= row);
+        shift_rows(&mut self.signs, row, moved);
+        shift_rows(&mut self.virtual_text, row, moved);
+        shift_rows(&mut self.highlights, row, moved);
+    }
+
+    pub fn status_segments(&self) -> Vec<&str>
+    {
+        self.status.values().map(String::as_str).collect()
+    }
+
+    // the most recent sign wins if several plugins mark the same line
+    pub fn sign(&self, row: usize) -> Option<&str>
+    {
+        self.signs
+            .get(&row)
+            .and_then(|signs| signs.last())
+            .map(|(_, text)| text.as_str())
+    }
+
+    pub fn sign_width(&self) -> usize
+    {
+        if self.signs.is_empty() {
+            0
+        } else {
+            SIGN_WIDTH
+        }
+    }
+
+    pub fn virtual_text(&self, row: usize) -> Vec<&str>
+    {
+        self.virtual_text.get(&row).map_or(vec![], |texts| {
+            texts.iter().map(|(_, text)| text.as_str()).collect()
+        })
+    }
+
+    pub fn highlights(&self, row: usize) -> Vec<&Highlight>
+    {
+        self.highlights.get(&row).map_or(vec![], |highlights| {
+            highlights.iter().map(|(_, highlight)| highlight).collect()
+        })
+    }
+}
+
+fn retain_others<T>(rows: &mut BTreeMap<usize, Vec<(String, T)>>, owner: &str)
+{
+    for entries in rows.values_mut() {
+        entries.retain(|(o, _)| o != owner);
+    }
+    rows.retain(|_, entries| !entries.is_empty());
+}
+
+// moves the entries from `row` on to the row `moved` returns for them, or
+// drops them if it returns `None`
+fn shift_rows<T, F>(rows: &mut BTreeMap<usize, Vec<T>>, row: usize, moved: F)
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let shifted = rows.split_off(&row);
+    for (r, entries) in shifted {
+        if let Some(r) = moved(r) {
+            rows.entry(r).or_default().extend(entries);
+        }
+    }
+}
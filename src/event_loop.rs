@@ -0,0 +1,467 @@
+// the sources of events the app waits for besides the jobs of plugins:
+// terminal input, signals, changes of watched files and the timers of
+// plugins. every source has its own thread that blocks until something
+// happens, nothing wakes up periodically. all events arrive on the channel of
+// the app
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use signal_hook::consts::{SIGTERM, SIGTSTP, SIGWINCH};
+use signal_hook::iterator::{Handle, Signals};
+
+use crate::input::Event;
+use crate::terminal::Terminal;
+
+// a pipe used to interrupt a thread waiting in `wait`
+pub struct Waker
+{
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Waker
+{
+    pub fn new() -> io::Result<Self>
+    {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in &fds {
+            unsafe {
+                let flags = libc::fcntl(*fd, libc::F_GETFL);
+                libc::fcntl(*fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        Ok(Self {
+            read: fds[0],
+            write: fds[1],
+        })
+    }
+
+    // readable until `reset` is called
+    pub fn fd(&self) -> RawFd
+    {
+        self.read
+    }
+
+    pub fn wake(&self)
+    {
+        unsafe {
+            libc::write(self.write, [0u8].as_ptr() as *const libc::c_void, 1);
+        }
+    }
+
+    pub fn reset(&self)
+    {
+        let mut buffer = [0u8; 64];
+        while unsafe {
+            libc::read(
+                self.read,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        } > 0
+        {}
+    }
+}
+
+impl Drop for Waker
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+// a timer started by a plugin, see `crate::job`
+pub struct Timer
+{
+    pub owner: String,
+    pub id: String,
+    pub generation: u64,
+    pub interval: Duration,
+    pub repeat: bool,
+    // cleared to cancel the timer
+    pub active: Arc<AtomicBool>,
+}
+
+struct Pending
+{
+    deadline: Instant,
+    timer: Timer,
+}
+
+// the timers waiting to fire, all of them are run by a single thread of the
+// event loop
+#[derive(Clone)]
+pub struct TimerQueue
+{
+    pending: Arc<Mutex<BinaryHeap<Pending>>>,
+    // woken when a timer is added, so the thread waits for the right one
+    changed: Arc<Waker>,
+}
+
+pub struct EventLoop
+{
+    sender: Sender<Event>,
+    // woken once on `stop` and never reset, so every thread sees it
+    stop: Arc<Waker>,
+    // woken on SIGWINCH, the terminal backends report the new size
+    resize: Arc<Waker>,
+    signals: Option<Handle>,
+    timers: TimerQueue,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl EventLoop
+{
+    pub fn new(sender: Sender<Event>) -> Self
+    {
+        let waker = || Arc::new(Waker::new().expect("could not create pipe"));
+        let mut event_loop = Self {
+            sender,
+            stop: waker(),
+            resize: waker(),
+            signals: None,
+            timers: TimerQueue {
+                pending: Arc::new(Mutex::new(BinaryHeap::new())),
+                changed: waker(),
+            },
+            threads: vec![],
+        };
+        event_loop.run_timers();
+        event_loop
+    }
+
+    // for sources of events outside of the event loop, such as plugin
+    // processes
+    pub fn sender(&self) -> Sender<Event>
+    {
+        self.sender.clone()
+    }
+
+    pub fn timers(&self) -> TimerQueue
+    {
+        self.timers.clone()
+    }
+
+    // sleeps until the next timer is due or a new one is added
+    fn run_timers(&mut self)
+    {
+        let sender = self.sender.clone();
+        let stop = Arc::clone(&self.stop);
+        let timers = self.timers.clone();
+        self.threads.push(thread::spawn(move || {
+            let changed = timers.changed.fd();
+            while let Some(ready) = wait(&[changed], &stop, timers.fire(&sender)) {
+                if ready[0] {
+                    timers.changed.reset();
+                }
+            }
+        }));
+    }
+
+    // reads the terminal whenever there is input. if the terminal goes away
+    // the app is asked to quit
+    pub fn listen(&mut self, terminal: Arc<dyn Terminal + Sync + Send>)
+    {
+        let sender = self.sender.clone();
+        let stop = Arc::clone(&self.stop);
+        let resize = Arc::clone(&self.resize);
+        self.threads.push(thread::spawn(move || {
+            while let Some(ready) = wait(&[terminal.input_fd(), resize.fd()], &stop, None) {
+                if ready[1] {
+                    resize.reset();
+                }
+                for event in terminal.read_events() {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+            sender.send(Event::Terminate).ok();
+        }));
+    }
+
+    // SIGTERM quits the app like `q`, SIGTSTP suspends it
+    pub fn watch_signals(&mut self)
+    {
+        let mut signals = match Signals::new([SIGWINCH, SIGTERM, SIGTSTP]) {
+            Ok(signals) => signals,
+            Err(err) => {
+                log!("could not register signal handlers: {}", err);
+                return;
+            }
+        };
+        self.signals = Some(signals.handle());
+
+        let sender = self.sender.clone();
+        let resize = Arc::clone(&self.resize);
+        self.threads.push(thread::spawn(move || {
+            for signal in signals.forever() {
+                let event = match signal {
+                    SIGWINCH => {
+                        resize.wake();
+                        continue;
+                    }
+                    SIGTERM => Event::Terminate,
+                    _ => Event::Suspend,
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+
+    // reports when `path` is written, moved or deleted by someone else. the
+    // directory is watched instead of the file, so the watch survives the
+    // file being replaced, e.g. by editors renaming a new version over it.
+    // watching only works on linux
+    #[cfg(target_os = "linux")]
+    pub fn watch(&mut self, path: &Path) -> io::Result<()>
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?
+            .to_os_string();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let directory = CString::new(directory.as_os_str().as_bytes())?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+        if unsafe { libc::inotify_add_watch(fd, directory.as_ptr(), mask) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        let sender = self.sender.clone();
+        let stop = Arc::clone(&self.stop);
+        let path = path.to_path_buf();
+        self.threads.push(thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while wait(&[fd], &stop, None).is_some() {
+                let read = unsafe {
+                    libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+                };
+                if read <= 0 {
+                    continue;
+                }
+                let changed = inotify_names(&buffer[..read as usize])
+                    .iter()
+                    .any(|name| name.as_bytes() == file_name.as_bytes());
+                if changed && sender.send(Event::FileChanged(path.clone())).is_err() {
+                    break;
+                }
+            }
+            unsafe { libc::close(fd) };
+        }));
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn watch(&mut self, _path: &Path) -> io::Result<()>
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "watching files is not supported",
+        ))
+    }
+
+    // ends and joins all threads
+    pub fn stop(&mut self)
+    {
+        self.stop.wake();
+        if let Some(signals) = self.signals.take() {
+            signals.close();
+        }
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for EventLoop
+{
+    fn drop(&mut self)
+    {
+        self.stop();
+    }
+}
+
+// the names of the files in a buffer of inotify events
+#[cfg(target_os = "linux")]
+fn inotify_names(mut buffer: &[u8]) -> Vec<std::ffi::OsString>
+{
+    use std::os::unix::ffi::OsStrExt;
+
+    let header = std::mem::size_of::<libc::inotify_event>();
+    let mut names = vec![];
+    while buffer.len() >= header {
+        let event =
+            unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::inotify_event) };
+        let end = (header + event.len as usize).min(buffer.len());
+        // the name is padded with zeros
+        let name = &buffer[header..end];
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        names.push(std::ffi::OsStr::from_bytes(&name[..len]).to_os_string());
+        buffer = &buffer[end..];
+    }
+    names
+}
+
+// the terminal the backends read from. stdin may be redirected, in which case
+// the controlling terminal is used
+pub fn tty_fd() -> RawFd
+{
+    lazy_static! {
+        static ref TTY: RawFd = {
+            if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+                libc::STDIN_FILENO
+            } else {
+                File::open("/dev/tty")
+                    .map(IntoRawFd::into_raw_fd)
+                    .unwrap_or(libc::STDIN_FILENO)
+            }
+        };
+    }
+    *TTY
+}
+
+impl TimerQueue
+{
+    // the first event is sent after `interval`
+    pub fn add(&self, timer: Timer)
+    {
+        let deadline = Instant::now() + timer.interval;
+        self.pending
+            .lock()
+            .unwrap()
+            .push(Pending { deadline, timer });
+        self.changed.wake();
+    }
+
+    // sends the events of the timers that are due and returns how long until
+    // the next one is. cancelled timers are dropped on the way
+    fn fire(&self, sender: &Sender<Event>) -> Option<Duration>
+    {
+        let mut pending = self.pending.lock().unwrap();
+        let now = Instant::now();
+        while let Some(next) = pending.peek() {
+            if next.deadline > now {
+                return Some(next.deadline - now);
+            }
+            let Pending { deadline, timer } = pending.pop().unwrap();
+            if !timer.active.load(atomic::Ordering::Relaxed) {
+                continue;
+            }
+            sender
+                .send(Event::Timer {
+                    owner: timer.owner.clone(),
+                    id: timer.id.clone(),
+                    generation: timer.generation,
+                })
+                .ok();
+            if timer.repeat {
+                // a timer that fell behind does not fire in a burst
+                let deadline = (deadline + timer.interval).max(now);
+                pending.push(Pending { deadline, timer });
+            }
+        }
+        None
+    }
+}
+
+// the heap is a max-heap, so the earliest deadline has to compare greatest
+impl Ord for Pending
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+impl PartialOrd for Pending
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Pending {}
+
+// blocks until one of `fds` can be read and returns which ones, or `None` once
+// `stop` was woken or one of `fds` was closed. after `timeout` none of them
+// is ready
+pub fn wait(fds: &[RawFd], stop: &Waker, timeout: Option<Duration>) -> Option<Vec<bool>>
+{
+    // rounded up, waking up too early would only wait again
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout
+            .as_micros()
+            .div_ceil(1000)
+            .min(libc::c_int::MAX as u128) as libc::c_int
+    });
+    let mut polled = fds
+        .iter()
+        .chain(std::iter::once(&stop.fd()))
+        .map(|fd| libc::pollfd {
+            fd: *fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    loop {
+        let result =
+            unsafe { libc::poll(polled.as_mut_ptr(), polled.len() as libc::nfds_t, timeout) };
+        if result < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return None;
+        }
+        let (stopped, ready) = polled.split_last().unwrap();
+        let closed = |fd: &libc::pollfd| {
+            fd.revents & libc::POLLIN == 0
+                && fd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0
+        };
+        if stopped.revents != 0 || ready.iter().any(closed) {
+            return None;
+        }
+        return Some(ready.iter().map(|fd| fd.revents != 0).collect());
+    }
+}
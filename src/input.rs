@@ -1,10 +1,37 @@
+use std::path::PathBuf;
+
 pub use libloe::input::CursorMove;
 
+use crate::job::JobOutput;
+
 #[derive(Debug, PartialEq)]
 pub enum Event
 {
     Key(Key),
     Resize,
+    // output of a background job started by the plugin `owner`
+    Job
+    {
+        owner: String,
+        id: String,
+        output: JobOutput,
+    },
+    // `generation` tells the timer apart from earlier ones with the same id
+    Timer
+    {
+        owner: String,
+        id: String,
+        generation: u64,
+    },
+    // a plugin process sent notifications, they are applied with the other
+    // requests of plugins
+    PluginRequest,
+    // a watched file was changed by another program
+    FileChanged(PathBuf),
+    // SIGTERM or the terminal went away
+    Terminate,
+    // SIGTSTP
+    Suspend,
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -0,0 +1,343 @@
+// background work for plugins. jobs are external processes whose output and
+// exit status arrive as events on the channel of the app, timers send an
+// event after their interval from the thread of the event loop. both are
+// identified by the name of the plugin that started them and an id the plugin
+// chose, so plugins cannot interfere with each other
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::event_loop::{self, TimerQueue, Waker};
+use crate::input::Event;
+
+// timers with a shorter interval are slowed down to this
+pub const MIN_TIMER_INTERVAL: Duration = Duration::from_millis(10);
+
+type JobKey = (String, String);
+
+#[derive(Debug, PartialEq)]
+pub enum JobOutput
+{
+    Stdout(String),
+    Stderr(String),
+    // `None` if the process was killed by a signal
+    Exit(Option<i32>),
+}
+
+pub struct Jobs
+{
+    sender: Sender<Event>,
+    processes: HashMap<JobKey, Process>,
+    timers: HashMap<JobKey, Timer>,
+    queue: TimerQueue,
+    // increased for every timer started
+    generation: u64,
+}
+
+// the child itself belongs to the thread waiting for it, jobs are killed by
+// their pid
+struct Process
+{
+    pid: libc::pid_t,
+    // set once the process is reaped, its pid may be reused after that
+    reaped: Arc<Mutex<bool>>,
+}
+
+struct Timer
+{
+    // cleared to cancel the timer
+    active: Arc<AtomicBool>,
+    repeat: bool,
+    generation: u64,
+}
+
+impl Jobs
+{
+    pub fn new(sender: Sender<Event>, queue: TimerQueue) -> Self
+    {
+        Self {
+            sender,
+            processes: HashMap::new(),
+            timers: HashMap::new(),
+            queue,
+            generation: 0,
+        }
+    }
+
+    // runs `command` in the background. every line it prints is sent as an
+    // event, followed by its exit status once it exited and what it printed
+    // was read
+    pub fn start(
+        &mut self,
+        owner: &str,
+        id: String,
+        command: &str,
+        args: &[String],
+    ) -> Result<(), String>
+    {
+        let key = (owner.to_string(), id);
+        if self.processes.contains_key(&key) {
+            return Err(format!("job `{}` is already running", key.1));
+        }
+        let exited =
+            Waker::new().map_err(|err| format!("could not start `{}`: {}", command, err))?;
+        let exited = Arc::new(exited);
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("could not start `{}`: {}", command, err))?;
+
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let errors = forward(
+            stderr,
+            self.sender.clone(),
+            key.clone(),
+            JobOutput::Stderr,
+            Arc::clone(&exited),
+        );
+        let output = forward(
+            stdout,
+            self.sender.clone(),
+            key.clone(),
+            JobOutput::Stdout,
+            Arc::clone(&exited),
+        );
+        let process = Process {
+            pid: child.id() as libc::pid_t,
+            reaped: Arc::new(Mutex::new(false)),
+        };
+
+        let sender = self.sender.clone();
+        let reaped = Arc::clone(&process.reaped);
+        let (owner, id) = key.clone();
+        // the exit does not wait for the streams to be closed, a process the
+        // job left behind may still hold them
+        thread::spawn(move || {
+            // blocks until the process exited without reaping it, so `kill`
+            // cannot hit another process with the same pid
+            let mut info = unsafe { std::mem::zeroed::<libc::siginfo_t>() };
+            while unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    child.id(),
+                    &mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            } < 0
+                && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+            {}
+            let status = {
+                let mut reaped = reaped.lock().unwrap();
+                *reaped = true;
+                child.wait().ok().and_then(|status| status.code())
+            };
+            exited.wake();
+            output.join().ok();
+            errors.join().ok();
+            sender
+                .send(Event::Job {
+                    owner,
+                    id,
+                    output: JobOutput::Exit(status),
+                })
+                .ok();
+        });
+
+        self.processes.insert(key, process);
+        Ok(())
+    }
+
+    // kills the job, its exit is reported as usual
+    pub fn stop(&mut self, owner: &str, id: &str) -> bool
+    {
+        match self.processes.get(&(owner.to_string(), id.to_string())) {
+            Some(process) => {
+                process.kill();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // forgets a job after its exit event arrived
+    pub fn finished(&mut self, owner: &str, id: &str)
+    {
+        self.processes.remove(&(owner.to_string(), id.to_string()));
+    }
+
+    // sends a timer event every `interval`, or only once unless `repeat` is
+    // set. starting a timer with the id of a running one replaces it
+    pub fn start_timer(&mut self, owner: &str, id: String, interval: Duration, repeat: bool)
+    {
+        let key = (owner.to_string(), id);
+        let active = Arc::new(AtomicBool::new(true));
+        self.generation += 1;
+        let generation = self.generation;
+        let timer = Timer {
+            active: Arc::clone(&active),
+            repeat,
+            generation,
+        };
+        if let Some(previous) = self.timers.insert(key.clone(), timer) {
+            previous.active.store(false, Ordering::Relaxed);
+        }
+
+        let (owner, id) = key;
+        self.queue.add(event_loop::Timer {
+            owner,
+            id,
+            generation,
+            interval,
+            repeat,
+            active,
+        });
+    }
+
+    pub fn stop_timer(&mut self, owner: &str, id: &str) -> bool
+    {
+        match self.timers.remove(&(owner.to_string(), id.to_string())) {
+            Some(timer) => {
+                timer.active.store(false, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // whether a timer event still belongs to a running timer and not to one
+    // it replaced. one-shot timers are forgotten once they fired
+    pub fn timer_fired(&mut self, owner: &str, id: &str, generation: u64) -> bool
+    {
+        let key = (owner.to_string(), id.to_string());
+        match self.timers.get(&key) {
+            Some(timer) if timer.generation == generation => {
+                if !timer.repeat {
+                    self.timers.remove(&key);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // stops everything a plugin started, used when it is unloaded
+    pub fn stop_all(&mut self, owner: &str)
+    {
+        let jobs = self
+            .processes
+            .keys()
+            .chain(self.timers.keys())
+            .filter(|(o, _)| o == owner)
+            .map(|(_, id)| id.clone())
+            .collect::<Vec<_>>();
+        for id in jobs {
+            self.stop(owner, &id);
+            self.stop_timer(owner, &id);
+        }
+    }
+}
+
+impl Drop for Jobs
+{
+    fn drop(&mut self)
+    {
+        for process in self.processes.values() {
+            process.kill();
+        }
+        for timer in self.timers.values() {
+            timer.active.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Process
+{
+    fn kill(&self)
+    {
+        let reaped = self.reaped.lock().unwrap();
+        if !*reaped {
+            unsafe { libc::kill(self.pid, libc::SIGKILL) };
+        }
+    }
+}
+
+// sends every line of `stream` as job output. once `exited` is woken only
+// what is still in the pipe is read, it is not waited for the pipe to close
+fn forward<R, F>(
+    mut stream: R,
+    sender: Sender<Event>,
+    key: JobKey,
+    output: F,
+    exited: Arc<Waker>,
+) -> thread::JoinHandle<()>
+where
+    R: Read + AsRawFd + Send + 'static,
+    F: Fn(String) -> JobOutput + Send + 'static,
+{
+    thread::spawn(move || {
+        let fd = stream.as_raw_fd();
+        let mut line = vec![];
+        let mut chunk = [0; 4096];
+        // returns the lines completed by `bytes`, a last line without a
+        // newline is only complete at the end
+        let mut split = |bytes: &[u8], end: bool| {
+            line.extend_from_slice(bytes);
+            let mut lines = vec![];
+            while let Some(at) = line.iter().position(|b| *b == b'\n') {
+                let rest = line.split_off(at + 1);
+                lines.push(std::mem::replace(&mut line, rest));
+            }
+            if end && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            lines
+        };
+        let send = |lines: Vec<Vec<u8>>| {
+            lines.into_iter().all(|line| {
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches('\n').trim_end_matches('\r');
+                let event = Event::Job {
+                    owner: key.0.clone(),
+                    id: key.1.clone(),
+                    output: output(line.to_string()),
+                };
+                sender.send(event).is_ok()
+            })
+        };
+
+        let mut draining = false;
+        loop {
+            // after the exit the pipe is read without blocking until it is
+            // empty
+            if !draining && event_loop::wait(&[fd], &exited, None).is_none() {
+                draining = true;
+                unsafe {
+                    let flags = libc::fcntl(fd, libc::F_GETFL);
+                    libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                }
+            }
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(len) => {
+                    if !send(split(&chunk[..len], false)) {
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        send(split(&[], true));
+    })
+}
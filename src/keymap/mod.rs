@@ -0,0 +1,220 @@
+mod trie;
+
+use std::collections::HashMap;
+
+use crate::input::Key;
+
+pub use self::trie::{KeyTrie, Lookup};
+
+pub type KeySequence = Vec<Key>;
+
+const MODES: &[&str] = &["view", "insert", "command"];
+
+// bindings shared by all modes
+const COMMON_BINDINGS: &[(&str, &str)] = &[
+    ("<Up>", "cursor-up"),
+    ("<Down>", "cursor-down"),
+    ("<Left>", "cursor-left"),
+    ("<Right>", "cursor-right"),
+    ("<Home>", "line-start"),
+    ("<End>", "line-end"),
+    ("<PageUp>", "page-up"),
+    ("<PageDown>", "page-down"),
+    ("<C-s>", "w"),
+    ("<C-c>", "cancel"),
+];
+
+const DEFAULT_BINDINGS: &[(&str, &str, &str)] = &[
+    ("view", ":", "mode command"),
+    ("view", "i", "mode insert"),
+    ("view", "<Insert>", "mode insert"),
+    ("insert", "<Insert>", "mode view"),
+    ("insert", "<Tab>", "indent"),
+    ("command", "<Tab>", "complete"),
+];
+
+pub struct Keymap
+{
+    modes: HashMap<String, KeyTrie>,
+    // modes of the `[keymap.<mode>]` sections, plugins may still define them
+    configured: Vec<String>,
+}
+
+impl Keymap
+{
+    pub fn new() -> Self
+    {
+        let mut keymap = Self {
+            modes: HashMap::new(),
+            configured: vec![],
+        };
+        for mode in MODES {
+            for (keys, command) in COMMON_BINDINGS {
+                keymap.map(mode, parse_keys(keys).unwrap(), command.to_string());
+            }
+        }
+        for (mode, keys, command) in DEFAULT_BINDINGS {
+            keymap.map(mode, parse_keys(keys).unwrap(), command.to_string());
+        }
+        keymap
+    }
+
+    // applies the `[keymap.<mode>]` sections of the configuration on top of
+    // the default bindings, invalid key sequences are skipped and reported.
+    // the names of the modes are checked by `unknown_modes` once plugins are
+    // loaded
+    pub fn from_config(sections: &HashMap<String, HashMap<String, String>>) -> (Self, Vec<String>)
+    {
+        let mut keymap = Self::new();
+        let mut errors = vec![];
+        for (mode, bindings) in sections {
+            keymap.configured.push(mode.clone());
+            for (keys, command) in bindings {
+                match parse_keys(keys) {
+                    Ok(keys) => keymap.map(mode, keys, command.clone()),
+                    Err(err) => errors.push(format!("keymap.{}: {}", mode, err)),
+                }
+            }
+        }
+        (keymap, errors)
+    }
+
+    // reports the configured modes that are neither built in nor in
+    // `defined`, their bindings can never be used
+    pub fn unknown_modes(&self, defined: &[&str]) -> Vec<String>
+    {
+        let mut unknown = self
+            .configured
+            .iter()
+            .filter(|mode| !MODES.contains(&mode.as_str()) && !defined.contains(&mode.as_str()))
+            .map(|mode| format!("keymap.{}: unknown mode `{}`", mode, mode))
+            .collect::<Vec<_>>();
+        unknown.sort();
+        unknown
+    }
+
+    // sets up a mode defined at runtime with the bindings shared by all modes
+    pub fn add_mode(&mut self, mode: &str)
+    {
+        for (keys, command) in COMMON_BINDINGS {
+            self.map_default(mode, parse_keys(keys).unwrap(), command.to_string());
+        }
+    }
+
+    pub fn map(&mut self, mode: &str, keys: KeySequence, command: String)
+    {
+        self.modes
+            .entry(mode.to_string())
+            .or_default()
+            .insert(&keys, command);
+    }
+
+    // binds `keys` only if there is no mapping for them yet
+    pub fn map_default(&mut self, mode: &str, keys: KeySequence, command: String)
+    {
+        if self.get(mode, &keys).is_none() {
+            self.map(mode, keys, command);
+        }
+    }
+
+    pub fn unmap(&mut self, mode: &str, keys: &[Key]) -> bool
+    {
+        self.modes
+            .get_mut(mode)
+            .and_then(|trie| trie.remove(keys))
+            .is_some()
+    }
+
+    pub fn get(&self, mode: &str, keys: &[Key]) -> Option<&String>
+    {
+        self.modes.get(mode).and_then(|trie| trie.get(keys))
+    }
+
+    pub fn lookup(&self, mode: &str, keys: &[Key]) -> Lookup<'_>
+    {
+        self.modes
+            .get(mode)
+            .map_or(Lookup::None, |trie| trie.lookup(keys))
+    }
+
+    pub fn list(&self, mode: &str) -> Vec<String>
+    {
+        let mut lines = self.modes.get(mode).map_or(vec![], |trie| {
+            trie.bindings()
+                .into_iter()
+                .map(|(keys, command)| format!("{} {} {}", mode, format_keys(&keys), command))
+                .collect::<Vec<_>>()
+        });
+        lines.sort();
+        lines
+    }
+}
+
+// parses key notations such as `gg`, `<C-s>`, `<Home>` or `<Esc>:w<CR>`
+pub fn parse_keys(notation: &str) -> Result<KeySequence, String>
+{
+    let mut keys = vec![];
+    let mut chars = notation.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '<' {
+            keys.push(Key::Char(c));
+            continue;
+        }
+
+        let name = chars.by_ref().take_while(|c| *c != '>').collect::<String>();
+        keys.push(parse_special(&name).ok_or_else(|| format!("unknown key `<{}>`", name))?);
+    }
+
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(keys)
+}
+
+fn parse_special(name: &str) -> Option<Key>
+{
+    let lower = name.to_lowercase();
+    if lower.starts_with("c-") && name.chars().count() == 3 {
+        return name
+            .chars()
+            .last()
+            .map(|c| Key::Ctrl(c.to_ascii_lowercase()));
+    }
+    let key = match lower.as_ref() {
+        "bs" | "backspace" => Key::Backspace,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "del" | "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "esc" => Key::Esc,
+        "tab" => Key::Tab,
+        "cr" | "enter" => Key::Enter,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+pub fn format_keys(keys: &[Key]) -> String
+{
+    keys.iter()
+        .map(|key| match key {
+            Key::Char(' ') => "<Space>".to_string(),
+            Key::Char('<') => "<lt>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("<C-{}>", c),
+            Key::Backspace => "<BS>".to_string(),
+            Key::Delete => "<Del>".to_string(),
+            Key::Enter => "<CR>".to_string(),
+            other => format!("<{:?}>", other),
+        })
+        .collect()
+}
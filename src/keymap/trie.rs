@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::input::Key;
+use crate::keymap::KeySequence;
+
+pub enum Lookup<'t>
+{
+    // no binding starts with the given keys
+    None,
+    // keys are the beginning of at least one longer binding
+    Prefix,
+    // keys are bound and no longer binding starts with them
+    Exact(&'t str),
+    // keys are bound but also the beginning of a longer binding
+    Ambiguous,
+}
+
+#[derive(Default)]
+pub struct KeyTrie
+{
+    command: Option<String>,
+    children: HashMap<Key, KeyTrie>,
+}
+
+impl KeyTrie
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, keys: &[Key], command: String) -> Option<String>
+    {
+        let node = keys.iter().fold(self, |node, key| {
+            node.children
+                .entry(key.clone())
+                .or_insert_with(KeyTrie::new)
+        });
+        node.command.replace(command)
+    }
+
+    pub fn remove(&mut self, keys: &[Key]) -> Option<String>
+    {
+        match keys.split_first() {
+            Some((key, rest)) => {
+                let child = self.children.get_mut(key)?;
+                let removed = child.remove(rest);
+                if child.is_empty() {
+                    self.children.remove(key);
+                }
+                removed
+            }
+            None => self.command.take(),
+        }
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_>
+    {
+        match self.node(keys) {
+            Some(node) => match (&node.command, node.children.is_empty()) {
+                (Some(command), true) => Lookup::Exact(command),
+                (Some(_), false) => Lookup::Ambiguous,
+                (None, false) => Lookup::Prefix,
+                (None, true) => Lookup::None,
+            },
+            None => Lookup::None,
+        }
+    }
+
+    pub fn get(&self, keys: &[Key]) -> Option<&String>
+    {
+        self.node(keys).and_then(|node| node.command.as_ref())
+    }
+
+    pub fn bindings(&self) -> Vec<(KeySequence, &String)>
+    {
+        let mut bindings = vec![];
+        self.collect(&mut vec![], &mut bindings);
+        bindings
+    }
+
+    fn node(&self, keys: &[Key]) -> Option<&KeyTrie>
+    {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+
+    fn is_empty(&self) -> bool
+    {
+        self.command.is_none() && self.children.is_empty()
+    }
+
+    fn collect<'t>(&'t self, prefix: &mut KeySequence, into: &mut Vec<(KeySequence, &'t String)>)
+    {
+        if let Some(command) = &self.command {
+            into.push((prefix.clone(), command));
+        }
+        for (key, child) in &self.children {
+            prefix.push(key.clone());
+            child.collect(prefix, into);
+            prefix.pop();
+        }
+    }
+}
@@ -4,6 +4,34 @@ pub enum Mode
     Insert,
     Command,
     Exit,
+    // a mode defined by a plugin. it has no behaviour of its own, keys only
+    // do what the keymap section of the same name binds them to
+    Custom(String),
+}
+
+impl Mode
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "view" => Some(Mode::View),
+            "insert" => Some(Mode::Insert),
+            "command" => Some(Mode::Command),
+            _ => None,
+        }
+    }
+
+    // identifier used for keymap sections and the `mode` command
+    pub fn name(&self) -> &str
+    {
+        match self {
+            Mode::View => "view",
+            Mode::Insert => "insert",
+            Mode::Command => "command",
+            Mode::Exit => "exit",
+            Mode::Custom(name) => name,
+        }
+    }
 }
 
 impl std::fmt::Display for Mode
@@ -14,6 +42,7 @@ impl std::fmt::Display for Mode
             Mode::Command => write!(f, "CMD"),
             Mode::Insert => write!(f, "INSERT"),
             Mode::View => write!(f, "VIEW"),
+            Mode::Custom(name) => write!(f, "{}", name.to_uppercase()),
             _ => write!(f, ""),
         }
     }
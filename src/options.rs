@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use toml::value::{Table, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionValue
+{
+    Bool(bool),
+    Number(i64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionKind
+{
+    Bool,
+    Number,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionScope
+{
+    Global,
+    Buffer,
+}
+
+pub struct OptionSpec
+{
+    pub name: &'static str,
+    pub alias: &'static str,
+    pub kind: OptionKind,
+    pub scope: OptionScope,
+    default: &'static str,
+}
+
+pub enum SetAction
+{
+    Show(&'static OptionSpec),
+    Toggle(&'static OptionSpec),
+    Assign(&'static OptionSpec, OptionValue),
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        name: "tabstop",
+        alias: "ts",
+        kind: OptionKind::Number,
+        scope: OptionScope::Buffer,
+        default: "8",
+    },
+    OptionSpec {
+        name: "shiftwidth",
+        alias: "sw",
+        kind: OptionKind::Number,
+        scope: OptionScope::Buffer,
+        default: "8",
+    },
+    OptionSpec {
+        name: "expandtab",
+        alias: "et",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Buffer,
+        default: "false",
+    },
+    OptionSpec {
+        name: "number",
+        alias: "nu",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "true",
+    },
+    OptionSpec {
+        name: "relativenumber",
+        alias: "rnu",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "false",
+    },
+    OptionSpec {
+        name: "wrap",
+        alias: "wrap",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "true",
+    },
+    OptionSpec {
+        name: "scrolloff",
+        alias: "so",
+        kind: OptionKind::Number,
+        scope: OptionScope::Global,
+        default: "0",
+    },
+    // the editor has no search of its own yet, so these two are only stored.
+    // a search ignores case with `ignorecase`, and `smartcase` only matters
+    // then: a pattern with an uppercase letter is matched exactly again
+    OptionSpec {
+        name: "ignorecase",
+        alias: "ic",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "false",
+    },
+    OptionSpec {
+        name: "smartcase",
+        alias: "scs",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "false",
+    },
+    OptionSpec {
+        name: "timeoutlen",
+        alias: "tm",
+        kind: OptionKind::Number,
+        scope: OptionScope::Global,
+        default: "1000",
+    },
+    OptionSpec {
+        name: "list",
+        alias: "list",
+        kind: OptionKind::Bool,
+        scope: OptionScope::Global,
+        default: "false",
+    },
+];
+
+impl OptionSpec
+{
+    pub fn find(name: &str) -> Option<&'static OptionSpec>
+    {
+        OPTIONS
+            .iter()
+            .find(|spec| spec.name == name || spec.alias == name)
+    }
+
+    pub fn all() -> &'static [OptionSpec]
+    {
+        OPTIONS
+    }
+
+    pub fn parse(&self, raw: &str) -> Result<OptionValue, String>
+    {
+        match self.kind {
+            OptionKind::Bool => match raw {
+                "true" | "on" | "1" => Ok(OptionValue::Bool(true)),
+                "false" | "off" | "0" => Ok(OptionValue::Bool(false)),
+                _ => Err(format!("`{}` expects a boolean, got `{}`", self.name, raw)),
+            },
+            OptionKind::Number => raw
+                .parse::<i64>()
+                .map(OptionValue::Number)
+                .map_err(|_| format!("`{}` expects a number, got `{}`", self.name, raw)),
+        }
+    }
+
+    fn parse_toml(&self, value: &Value) -> Result<OptionValue, String>
+    {
+        match (self.kind, value) {
+            (OptionKind::Bool, Value::Boolean(b)) => Ok(OptionValue::Bool(*b)),
+            (OptionKind::Number, Value::Integer(n)) => Ok(OptionValue::Number(*n)),
+            _ => Err(format!(
+                "invalid value `{}` for option `{}`",
+                value, self.name
+            )),
+        }
+    }
+
+    fn default_value(&self) -> OptionValue
+    {
+        self.parse(self.default).unwrap()
+    }
+}
+
+// parses a single argument of `:set`: `opt`, `noopt`, `opt!`, `opt?` and
+// `opt=value`
+pub fn parse_set_argument(arg: &str) -> Result<SetAction, String>
+{
+    let unknown = |name: &str| format!("unknown option `{}`", name);
+
+    if let Some(pos) = arg.find('=') {
+        let (name, raw) = (&arg[..pos], &arg[pos + 1..]);
+        let spec = OptionSpec::find(name).ok_or_else(|| unknown(name))?;
+        return Ok(SetAction::Assign(spec, spec.parse(raw)?));
+    }
+
+    if let Some(name) = arg.strip_suffix('!') {
+        let spec = OptionSpec::find(name).ok_or_else(|| unknown(name))?;
+        if spec.kind != OptionKind::Bool {
+            return Err(format!("`{}` is not a boolean option", spec.name));
+        }
+        return Ok(SetAction::Toggle(spec));
+    }
+
+    if let Some(name) = arg.strip_suffix('?') {
+        let spec = OptionSpec::find(name).ok_or_else(|| unknown(name))?;
+        return Ok(SetAction::Show(spec));
+    }
+
+    if let Some(spec) = OptionSpec::find(arg) {
+        return match spec.kind {
+            OptionKind::Bool => Ok(SetAction::Assign(spec, OptionValue::Bool(true))),
+            _ => Ok(SetAction::Show(spec)),
+        };
+    }
+
+    if let Some(name) = arg.strip_prefix("no") {
+        if let Some(spec) = OptionSpec::find(name) {
+            if spec.kind == OptionKind::Bool {
+                return Ok(SetAction::Assign(spec, OptionValue::Bool(false)));
+            }
+            return Err(format!("`{}` is not a boolean option", spec.name));
+        }
+    }
+
+    Err(unknown(arg))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options
+{
+    values: HashMap<&'static str, OptionValue>,
+}
+
+impl Options
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn defaults() -> Self
+    {
+        Self {
+            values: OPTIONS
+                .iter()
+                .map(|spec| (spec.name, spec.default_value()))
+                .collect(),
+        }
+    }
+
+    // reads the `[options]` table of the configuration, invalid entries are
+    // skipped and reported
+    pub fn from_table(table: &Table) -> (Self, Vec<String>)
+    {
+        let mut options = Self::defaults();
+        let mut errors = vec![];
+        for (name, value) in table {
+            match OptionSpec::find(name).ok_or_else(|| format!("unknown option `{}`", name)) {
+                Ok(spec) => match spec.parse_toml(value) {
+                    Ok(value) => options.set(spec, value),
+                    Err(err) => errors.push(err),
+                },
+                Err(err) => errors.push(err),
+            }
+        }
+        (options, errors)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&OptionValue>
+    {
+        OptionSpec::find(name).and_then(|spec| self.values.get(spec.name))
+    }
+
+    pub fn set(&mut self, spec: &'static OptionSpec, value: OptionValue)
+    {
+        self.values.insert(spec.name, value);
+    }
+}
+
+impl std::fmt::Display for OptionValue
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error>
+    {
+        match self {
+            OptionValue::Bool(b) => write!(f, "{}", b),
+            OptionValue::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl OptionValue
+{
+    pub fn as_bool(&self) -> bool
+    {
+        match self {
+            OptionValue::Bool(b) => *b,
+            OptionValue::Number(n) => *n != 0,
+        }
+    }
+
+    pub fn as_number(&self) -> i64
+    {
+        match self {
+            OptionValue::Bool(b) => *b as i64,
+            OptionValue::Number(n) => *n,
+        }
+    }
+}
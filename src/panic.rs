@@ -0,0 +1,23 @@
+// panics must neither scribble over the screen nor leave the terminal in raw
+// mode. the hook only writes them to the log, `main` reports a fatal panic
+// once the terminal has been restored
+
+use std::any::Any;
+use std::panic;
+
+pub fn install_hook()
+{
+    panic::set_hook(Box::new(|info| log!("panic: {}", info)));
+}
+
+// the text a panic was started with, if it has one
+pub fn message(panic: &(dyn Any + Send)) -> String
+{
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown cause".to_string()
+    }
+}
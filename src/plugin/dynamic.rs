@@ -1,69 +1,329 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 use std::path::Path;
+use std::ptr;
 
-use libloading::{Library, Symbol};
-use libloe::plugin::*;
+use libloading::Library;
+use loe_plugin::abi::*;
+use toml::value::Table;
 
 use crate::buffer::Buffer;
-use crate::plugin::{Plugin, PluginResult};
+use crate::plugin::host::{self, HOST_API};
+use crate::plugin::{
+    EventKind, KeyBinding, Plugin, PluginEvent, PluginManifest, PluginRequest, PluginResult,
+};
+
+const EDITOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// function pointers resolved once at load time. they stay valid as long as
+// `_library` is loaded, which is why both live in the same struct
+struct Symbols
+{
+    commands: CommandsFn,
+    dispatch: DispatchFn,
+    events: Option<EventsFn>,
+    notify: Option<NotifyFn>,
+    last_error: Option<LastErrorFn>,
+    unload: Option<UnloadFn>,
+    configure: Option<ConfigureFn>,
+    modes: Option<ModesFn>,
+    bindings: Option<BindingsFn>,
+}
 
 pub struct DynamicPlugin
 {
-    library: Library,
+    _library: Library,
+    manifest: PluginManifest,
+    symbols: Symbols,
+    // decorations queued through the host api during the last calls
+    requests: Vec<PluginRequest>,
+    // set once the plugin reported a panic
+    failure: Option<String>,
 }
 
 impl DynamicPlugin
 {
+    // the abi version is checked before anything else is read from the
+    // library, so plugins built against an incompatible interface are
+    // rejected instead of crashing later on
     pub fn load(path: &Path) -> PluginResult<Box<Self>>
     {
-        if let Ok(library) = Library::new(path) {
-            // TODO: cache library symbols
-            let plugin = Box::new(Self { library });
+        let library = Library::new(path).map_err(|err| format!("could not be loaded: {}", err))?;
 
-            Ok(plugin)
-        } else {
-            Err("plugin could not be loaded".to_string())
+        let abi_version = unsafe {
+            library
+                .get::<AbiVersionFn>(SYMBOL_ABI_VERSION)
+                .map(|abi_version| abi_version())
+                .map_err(|_| "does not export an abi version".to_string())?
+        };
+        if abi_version != LOE_PLUGIN_ABI_VERSION {
+            return Err(format!(
+                "abi version {} is not supported, expected {}",
+                abi_version, LOE_PLUGIN_ABI_VERSION
+            ));
+        }
+
+        let manifest = unsafe {
+            library
+                .get::<ManifestFn>(SYMBOL_MANIFEST)
+                .ok()
+                .and_then(|manifest| read_manifest(manifest()))
+                .ok_or_else(|| "does not export a valid manifest".to_string())?
+        };
+        if let Some(required) = &manifest.editor_version {
+            if !version_satisfied(required, EDITOR_VERSION) {
+                return Err(format!(
+                    "`{}` requires editor version {}, running {}",
+                    manifest.name, required, EDITOR_VERSION
+                ));
+            }
+        }
+
+        let symbols = unsafe {
+            Symbols {
+                commands: required(&library, SYMBOL_COMMANDS)?,
+                dispatch: required(&library, SYMBOL_DISPATCH)?,
+                events: optional(&library, SYMBOL_EVENTS),
+                notify: optional(&library, SYMBOL_NOTIFY),
+                last_error: optional(&library, SYMBOL_LAST_ERROR),
+                unload: optional(&library, SYMBOL_UNLOAD),
+                configure: optional(&library, SYMBOL_CONFIGURE),
+                modes: optional(&library, SYMBOL_MODES),
+                bindings: optional(&library, SYMBOL_BINDINGS),
+            }
+        };
+
+        Ok(Box::new(Self {
+            _library: library,
+            manifest,
+            symbols,
+            requests: vec![],
+            failure: None,
+        }))
+    }
+
+    pub fn is_library(path: &Path) -> bool
+    {
+        path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION)
+    }
+
+    fn last_error(&self) -> String
+    {
+        unsafe {
+            self.symbols
+                .last_error
+                .map(|last_error| last_error())
+                .filter(|err| !err.is_null())
+                .map_or_else(
+                    || format!("plugin `{}` failed", self.name()),
+                    |err| CStr::from_ptr(err).to_string_lossy().into_owned(),
+                )
+        }
+    }
+
+    fn status(&mut self, status: LoeStatus) -> PluginResult<()>
+    {
+        match status {
+            LOE_OK => Ok(()),
+            LOE_PANICKED => {
+                let err = self.last_error();
+                self.failure = Some(err.clone());
+                Err(err)
+            }
+            _ => Err(self.last_error()),
         }
     }
 }
 
 impl Plugin for DynamicPlugin
 {
-    fn name(&self) -> &'static str
+    fn name(&self) -> &str
     {
-        unsafe {
-            self.library
-                .get::<Symbol<NameCallback>>(b"name")
-                .map_or_else(|_| "ForeignPlugin <noname>", |name| name())
-        }
+        &self.manifest.name
+    }
+
+    fn manifest(&self) -> PluginManifest
+    {
+        self.manifest.clone()
     }
 
     fn commands(&self) -> Vec<String>
     {
-        unsafe {
-            self.library
-                .get::<Symbol<CommandsCallback>>(b"commands")
-                .map_or_else(|_| vec![], |commands| commands())
-        }
+        unsafe { string_list((self.symbols.commands)()) }
     }
 
     fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>
+    {
+        let cmd = CString::new(cmd).map_err(|_| "invalid command".to_string())?;
+        let status =
+            unsafe { (self.symbols.dispatch)(&HOST_API, host::handle(buffer), cmd.as_ptr()) };
+        self.requests.extend(host::take_requests());
+        self.status(status)
+    }
+
+    fn modes(&self) -> Vec<String>
     {
         unsafe {
-            self.library
-                .get::<Symbol<DispatchCallback>>(b"dispatch")
-                .map_or_else(
-                    |_| Err(format!("no dispatch function in plugin `{}`", self.name())),
-                    |dispatch| dispatch(buffer.inner_mut(), cmd),
-                )
+            self.symbols
+                .modes
+                .map_or_else(Vec::new, |modes| string_list(modes()))
         }
     }
 
-    fn unload(mut self)
+    fn bindings(&self) -> Vec<KeyBinding>
+    {
+        let bindings = unsafe {
+            self.symbols
+                .bindings
+                .map_or_else(Vec::new, |bindings| string_list(bindings()))
+        };
+        bindings
+            .iter()
+            .filter_map(|binding| {
+                let parsed = KeyBinding::parse(binding);
+                if parsed.is_none() {
+                    log!("{}: invalid binding `{}`", self.name(), binding);
+                }
+                parsed
+            })
+            .collect()
+    }
+
+    fn events(&self) -> Vec<EventKind>
     {
         unsafe {
-            if let Ok(unload) = self.library.get::<Symbol<UnloadCallback>>(b"unload") {
-                unload();
-            }
+            self.symbols
+                .events
+                .map_or_else(Vec::new, |events| string_list(events()))
+                .iter()
+                .filter_map(|name| EventKind::from_name(name))
+                .collect()
         }
     }
+
+    fn notify(&mut self, buffer: Option<&mut Buffer>, event: &PluginEvent) -> PluginResult<()>
+    {
+        let notify = match self.symbols.notify {
+            Some(notify) => notify,
+            None => return Ok(()),
+        };
+        let name = CString::new(event.kind().name()).unwrap();
+        let payload = CString::new(event.payload()).map_err(|_| "invalid payload".to_string())?;
+        let buffer = buffer.map_or(ptr::null_mut(), host::handle);
+        let status = unsafe { notify(&HOST_API, buffer, name.as_ptr(), payload.as_ptr()) };
+        self.requests.extend(host::take_requests());
+        self.status(status)
+    }
+
+    fn configure(&mut self, config: &Table) -> PluginResult<()>
+    {
+        let configure = match self.symbols.configure {
+            Some(configure) => configure,
+            None => return Ok(()),
+        };
+        let config = toml::to_string(config).map_err(|err| err.to_string())?;
+        let config = CString::new(config).map_err(|_| "invalid configuration".to_string())?;
+        let status = unsafe { configure(config.as_ptr()) };
+        self.status(status)
+    }
+
+    fn requests(&mut self) -> Vec<PluginRequest>
+    {
+        std::mem::take(&mut self.requests)
+    }
+
+    fn failure(&self) -> Option<String>
+    {
+        self.failure.clone()
+    }
+
+    fn unload(&mut self)
+    {
+        if let Some(unload) = self.symbols.unload.take() {
+            unsafe { unload() };
+        }
+    }
+}
+
+// plugins that never made it into the command manager still get the chance
+// to clean up before the library is closed
+impl Drop for DynamicPlugin
+{
+    fn drop(&mut self)
+    {
+        self.unload();
+    }
+}
+
+unsafe fn required<T: Copy>(library: &Library, symbol: &[u8]) -> PluginResult<T>
+{
+    library.get::<T>(symbol).map(|f| *f).map_err(|_| {
+        format!(
+            "missing required symbol `{}`",
+            String::from_utf8_lossy(&symbol[..symbol.len() - 1])
+        )
+    })
+}
+
+unsafe fn optional<T: Copy>(library: &Library, symbol: &[u8]) -> Option<T>
+{
+    library.get::<T>(symbol).ok().map(|f| *f)
+}
+
+// copies a null-terminated list of strings owned by the plugin
+unsafe fn string_list(list: *const *const c_char) -> Vec<String>
+{
+    let mut strings = vec![];
+    if list.is_null() {
+        return strings;
+    }
+    let mut i = 0;
+    while !(*list.add(i)).is_null() {
+        strings.push(CStr::from_ptr(*list.add(i)).to_string_lossy().into_owned());
+        i += 1;
+    }
+    strings
+}
+
+unsafe fn read_manifest(manifest: *const LoePluginManifest) -> Option<PluginManifest>
+{
+    let manifest = manifest.as_ref()?;
+    if manifest.abi_version != LOE_PLUGIN_ABI_VERSION || manifest.name.is_null() {
+        return None;
+    }
+    let string = |ptr: *const c_char| {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+    };
+    Some(PluginManifest {
+        name: string(manifest.name)?,
+        version: string(manifest.version).unwrap_or_default(),
+        author: string(manifest.author).unwrap_or_default(),
+        description: string(manifest.description).unwrap_or_default(),
+        editor_version: string(manifest.editor_version),
+    })
+}
+
+// compares dotted version numbers, missing components count as zero
+fn version_satisfied(required: &str, actual: &str) -> bool
+{
+    let parse = |version: &str| {
+        version
+            .trim_start_matches(">=")
+            .trim()
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    let (mut required, mut actual) = (parse(required), parse(actual));
+    let len = required.len().max(actual.len());
+    required.resize(len, 0);
+    actual.resize(len, 0);
+    actual >= required
 }
@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use crate::terminal::Position;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventKind
+{
+    BufferOpened,
+    BeforeSave,
+    AfterSave,
+    TextChanged,
+    CursorMoved,
+    ModeChanged,
+    Startup,
+    Shutdown,
+    JobStdout,
+    JobStderr,
+    JobExit,
+    Timer,
+}
+
+#[derive(Clone, Debug)]
+pub enum PluginEvent
+{
+    BufferOpened(Option<PathBuf>),
+    BeforeSave(PathBuf),
+    AfterSave(PathBuf),
+    TextChanged,
+    CursorMoved(Position),
+    ModeChanged(String),
+    Startup,
+    Shutdown,
+    // jobs and timers are only reported to the plugin that started them
+    JobStdout
+    {
+        id: String,
+        line: String,
+    },
+    JobStderr
+    {
+        id: String,
+        line: String,
+    },
+    JobExit
+    {
+        id: String,
+        code: Option<i32>,
+    },
+    Timer(String),
+}
+
+const EVENT_NAMES: &[(EventKind, &str)] = &[
+    (EventKind::BufferOpened, "buffer-opened"),
+    (EventKind::BeforeSave, "before-save"),
+    (EventKind::AfterSave, "after-save"),
+    (EventKind::TextChanged, "text-changed"),
+    (EventKind::CursorMoved, "cursor-moved"),
+    (EventKind::ModeChanged, "mode-changed"),
+    (EventKind::Startup, "startup"),
+    (EventKind::Shutdown, "shutdown"),
+    (EventKind::JobStdout, "job-stdout"),
+    (EventKind::JobStderr, "job-stderr"),
+    (EventKind::JobExit, "job-exit"),
+    (EventKind::Timer, "timer"),
+];
+
+impl EventKind
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        EVENT_NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(kind, _)| *kind)
+    }
+
+    pub fn name(self) -> &'static str
+    {
+        EVENT_NAMES
+            .iter()
+            .find(|(kind, _)| *kind == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+}
+
+impl PluginEvent
+{
+    pub fn kind(&self) -> EventKind
+    {
+        match self {
+            PluginEvent::BufferOpened(_) => EventKind::BufferOpened,
+            PluginEvent::BeforeSave(_) => EventKind::BeforeSave,
+            PluginEvent::AfterSave(_) => EventKind::AfterSave,
+            PluginEvent::TextChanged => EventKind::TextChanged,
+            PluginEvent::CursorMoved(_) => EventKind::CursorMoved,
+            PluginEvent::ModeChanged(_) => EventKind::ModeChanged,
+            PluginEvent::Startup => EventKind::Startup,
+            PluginEvent::Shutdown => EventKind::Shutdown,
+            PluginEvent::JobStdout { .. } => EventKind::JobStdout,
+            PluginEvent::JobStderr { .. } => EventKind::JobStderr,
+            PluginEvent::JobExit { .. } => EventKind::JobExit,
+            PluginEvent::Timer(_) => EventKind::Timer,
+        }
+    }
+
+    // event data as a plain string for plugins outside of the editor
+    pub fn payload(&self) -> String
+    {
+        match self {
+            PluginEvent::BufferOpened(Some(path))
+            | PluginEvent::BeforeSave(path)
+            | PluginEvent::AfterSave(path) => path.display().to_string(),
+            PluginEvent::CursorMoved((x, y)) => format!("{} {}", x, y),
+            PluginEvent::ModeChanged(mode) => mode.to_string(),
+            // the id comes first, job ids cannot contain spaces
+            PluginEvent::JobStdout { id, line } | PluginEvent::JobStderr { id, line } => {
+                format!("{} {}", id, line)
+            }
+            PluginEvent::JobExit { id, code } => format!("{} {}", id, code.unwrap_or(-1)),
+            PluginEvent::Timer(id) => id.clone(),
+            _ => String::new(),
+        }
+    }
+}
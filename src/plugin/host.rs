@@ -0,0 +1,258 @@
+// implementation of the functions the editor exposes to dynamic plugins.
+// `LoeBuffer` handles are pointers to `Buffer` and only valid during the
+// plugin call they were passed to. decorations are queued as requests and
+// collected by the plugin after each call with `take_requests`
+
+use std::cell::RefCell;
+
+use loe_plugin::abi::*;
+
+use crate::buffer::Buffer;
+use crate::input::CursorMove;
+use crate::plugin::PluginRequest;
+
+thread_local! {
+    static REQUESTS: RefCell<Vec<PluginRequest>> = const { RefCell::new(vec![]) };
+}
+
+pub static HOST_API: LoeHostApi = LoeHostApi {
+    abi_version: LOE_PLUGIN_ABI_VERSION,
+    size: std::mem::size_of::<LoeHostApi>(),
+    line_count,
+    get_line,
+    set_line,
+    insert_line,
+    remove_line,
+    get_cursor,
+    set_cursor,
+    set_status,
+    add_sign,
+    add_virtual_text,
+    add_highlight,
+    clear_decorations,
+    start_job,
+    stop_job,
+    start_timer,
+    stop_timer,
+};
+
+pub fn take_requests() -> Vec<PluginRequest>
+{
+    REQUESTS.with(|requests| std::mem::take(&mut *requests.borrow_mut()))
+}
+
+fn request(request: PluginRequest)
+{
+    REQUESTS.with(|requests| requests.borrow_mut().push(request));
+}
+
+pub fn handle(buffer: &mut Buffer) -> *mut LoeBuffer
+{
+    buffer as *mut Buffer as *mut LoeBuffer
+}
+
+fn buffer<'a>(raw: *const LoeBuffer) -> Option<&'a Buffer>
+{
+    unsafe { (raw as *const Buffer).as_ref() }
+}
+
+fn buffer_mut<'a>(raw: *mut LoeBuffer) -> Option<&'a mut Buffer>
+{
+    unsafe { (raw as *mut Buffer).as_mut() }
+}
+
+fn owned(line: LoeStr) -> Option<String>
+{
+    unsafe { line.as_str().map(str::to_string) }
+}
+
+fn status(done: bool) -> LoeStatus
+{
+    if done {
+        LOE_OK
+    } else {
+        LOE_OUT_OF_RANGE
+    }
+}
+
+extern "C" fn line_count(raw: *const LoeBuffer) -> usize
+{
+    buffer(raw).map_or(0, Buffer::content_len)
+}
+
+extern "C" fn get_line(raw: *const LoeBuffer, row: usize, line: *mut LoeStr) -> LoeStatus
+{
+    match (buffer(raw), unsafe { line.as_mut() }) {
+        (Some(buffer), Some(line)) => match buffer.line(row) {
+            Some(content) => {
+                *line = LoeStr::new(content);
+                LOE_OK
+            }
+            None => LOE_OUT_OF_RANGE,
+        },
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn set_line(raw: *mut LoeBuffer, row: usize, line: LoeStr) -> LoeStatus
+{
+    match (buffer_mut(raw), owned(line)) {
+        (Some(buffer), Some(line)) => status(buffer.set_line(row, line)),
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn insert_line(raw: *mut LoeBuffer, row: usize, line: LoeStr) -> LoeStatus
+{
+    match (buffer_mut(raw), owned(line)) {
+        (Some(buffer), Some(line)) => status(buffer.insert_line(row, line)),
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn remove_line(raw: *mut LoeBuffer, row: usize) -> LoeStatus
+{
+    match buffer_mut(raw) {
+        Some(buffer) => status(buffer.remove_line(row)),
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn get_cursor(raw: *const LoeBuffer, col: *mut i64, row: *mut i64)
+{
+    if let Some(buffer) = buffer(raw) {
+        let (x, y) = buffer.get_cursor();
+        unsafe {
+            if let Some(col) = col.as_mut() {
+                *col = x;
+            }
+            if let Some(row) = row.as_mut() {
+                *row = y;
+            }
+        }
+    }
+}
+
+extern "C" fn set_cursor(raw: *mut LoeBuffer, col: i64, row: i64)
+{
+    if let Some(buffer) = buffer_mut(raw) {
+        buffer.move_cursor(CursorMove::Absolute(col, row));
+    }
+}
+
+extern "C" fn set_status(id: LoeStr, text: LoeStr) -> LoeStatus
+{
+    match (owned(id), owned(text)) {
+        (Some(id), Some(text)) => {
+            request(PluginRequest::SetStatus { id, text });
+            LOE_OK
+        }
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn add_sign(row: usize, text: LoeStr) -> LoeStatus
+{
+    match owned(text) {
+        Some(text) => {
+            request(PluginRequest::AddSign { row, text });
+            LOE_OK
+        }
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn add_virtual_text(row: usize, text: LoeStr) -> LoeStatus
+{
+    match owned(text) {
+        Some(text) => {
+            request(PluginRequest::AddVirtualText { row, text });
+            LOE_OK
+        }
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn add_highlight(row: usize, start: usize, end: usize, group: LoeStr) -> LoeStatus
+{
+    match owned(group) {
+        Some(group) if start <= end => {
+            request(PluginRequest::AddHighlight {
+                row,
+                start,
+                end,
+                group,
+            });
+            LOE_OK
+        }
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn clear_decorations()
+{
+    request(PluginRequest::ClearDecorations);
+}
+
+extern "C" fn start_job(
+    id: LoeStr,
+    command: LoeStr,
+    args: *const LoeStr,
+    arg_count: usize,
+) -> LoeStatus
+{
+    let args = if arg_count == 0 {
+        Some(vec![])
+    } else if args.is_null() {
+        None
+    } else {
+        unsafe { std::slice::from_raw_parts(args, arg_count) }
+            .iter()
+            .map(|arg| owned(*arg))
+            .collect::<Option<Vec<_>>>()
+    };
+    match (owned(id), owned(command), args) {
+        (Some(id), Some(command), Some(args)) => {
+            request(PluginRequest::StartJob { id, command, args });
+            LOE_OK
+        }
+        _ => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn stop_job(id: LoeStr) -> LoeStatus
+{
+    match owned(id) {
+        Some(id) => {
+            request(PluginRequest::StopJob(id));
+            LOE_OK
+        }
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn start_timer(id: LoeStr, interval_ms: u64, repeat: bool) -> LoeStatus
+{
+    match owned(id) {
+        Some(id) => {
+            request(PluginRequest::StartTimer {
+                id,
+                interval: interval_ms,
+                repeat,
+            });
+            LOE_OK
+        }
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
+
+extern "C" fn stop_timer(id: LoeStr) -> LoeStatus
+{
+    match owned(id) {
+        Some(id) => {
+            request(PluginRequest::StopTimer(id));
+            LOE_OK
+        }
+        None => LOE_INVALID_ARGUMENT,
+    }
+}
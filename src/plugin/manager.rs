@@ -1,16 +1,66 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::rc::Rc;
 use std::sync::Mutex;
 
 use crate::buffer::Buffer;
-use crate::plugin::Plugin;
+use crate::panic;
+use crate::plugin::{
+    EventKind, KeyBinding, Plugin, PluginEvent, PluginManifest, PluginRequest, PluginResult,
+};
 
 pub type SharedPlugin = Rc<Mutex<Box<dyn Plugin>>>;
 
+// source of plugins compiled into the editor, they cannot be unloaded
+pub const BUILTIN_SOURCE: &str = "builtin";
+
+#[derive(Clone, Debug)]
+pub enum PluginState
+{
+    Loaded,
+    // refused at load time, e.g. because of an incompatible abi
+    Rejected(String),
+    // loaded, but it could not be configured or its commands could not be
+    // registered
+    Failed(String),
+}
+
+pub struct PluginStatus
+{
+    pub manifest: PluginManifest,
+    pub source: String,
+    pub state: PluginState,
+}
+
+// what is left of a plugin taken out of the manager, either because it was
+// unloaded or because it panicked
+pub struct Detached
+{
+    pub name: String,
+    pub source: String,
+    // the commands it provided that no other plugin provides in its place
+    pub commands: Vec<String>,
+    pub bindings: Vec<KeyBinding>,
+}
+
 pub struct CommandManager
 {
     plugins: Vec<SharedPlugin>,
     commands: HashMap<String, SharedPlugin>,
+    // earlier owners of commands taken over by a plugin of higher priority,
+    // the most recent owner last. they get the command back once the plugin
+    // that took it is gone
+    displaced: HashMap<String, Vec<SharedPlugin>>,
+    subscriptions: HashMap<EventKind, Vec<SharedPlugin>>,
+    statuses: Vec<PluginStatus>,
+    // commands of the editor itself, plugins may only take them if they are
+    // listed in `overrides`
+    reserved: HashSet<String>,
+    overrides: HashSet<String>,
+    priority: Vec<String>,
+    // plugins disabled after a panic together with the reason, until the
+    // editor picks them up with `take_disabled`
+    disabled: Vec<(Detached, String)>,
 }
 
 impl CommandManager
@@ -20,43 +70,388 @@ impl CommandManager
         Self {
             plugins: vec![],
             commands: HashMap::new(),
+            displaced: HashMap::new(),
+            subscriptions: HashMap::new(),
+            statuses: vec![],
+            reserved: HashSet::new(),
+            overrides: HashSet::new(),
+            priority: vec![],
+            disabled: vec![],
+        }
+    }
+
+    pub fn reserve(&mut self, commands: &[&str])
+    {
+        self.reserved
+            .extend(commands.iter().map(|cmd| cmd.to_string()));
+    }
+
+    pub fn allow_override(&mut self, commands: &[String])
+    {
+        self.overrides.extend(commands.iter().cloned());
+    }
+
+    pub fn set_priority(&mut self, plugins: Vec<String>)
+    {
+        self.priority = plugins;
+    }
+
+    // `source` describes where the plugin came from, e.g. the library path
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>, source: &str) -> Result<(), String>
+    {
+        let manifest = plugin.manifest();
+        let added = self.register_plugin(plugin);
+        self.statuses.push(PluginStatus {
+            manifest,
+            source: source.to_string(),
+            state: match &added {
+                Ok(()) => PluginState::Loaded,
+                Err(err) => PluginState::Failed(err.clone()),
+            },
+        });
+        added
+    }
+
+    pub fn reject(&mut self, source: &str, reason: String)
+    {
+        self.statuses.push(PluginStatus {
+            manifest: PluginManifest::default(),
+            source: source.to_string(),
+            state: PluginState::Rejected(reason),
+        });
+    }
+
+    // the plugin was loaded, but could not be set up, e.g. because it refused
+    // its configuration
+    pub fn fail(&mut self, manifest: PluginManifest, source: &str, reason: String)
+    {
+        self.statuses.push(PluginStatus {
+            manifest,
+            source: source.to_string(),
+            state: PluginState::Failed(reason),
+        });
+    }
+
+    // unregisters the commands and subscriptions of the plugin named `name`
+    // and unloads it
+    pub fn remove_plugin(&mut self, name: &str) -> Result<Detached, String>
+    {
+        let status = self
+            .statuses
+            .iter()
+            .position(|status| {
+                status.manifest.name == name && matches!(status.state, PluginState::Loaded)
+            })
+            .ok_or_else(|| format!("no plugin named `{}` is loaded", name))?;
+        if self.statuses[status].source == BUILTIN_SOURCE {
+            return Err(format!("`{}` is built in and cannot be unloaded", name));
+        }
+
+        let plugin = self.find(name)?;
+        let commands = self.detach(&plugin);
+        let bindings = plugin.lock().unwrap().bindings();
+        plugin.lock().unwrap().unload();
+        let status = self.statuses.remove(status);
+        Ok(Detached {
+            name: name.to_string(),
+            source: status.source,
+            commands,
+            bindings,
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<SharedPlugin, String>
+    {
+        self.plugins
+            .iter()
+            .find(|plugin| plugin.lock().unwrap().name() == name)
+            .cloned()
+            .ok_or_else(|| format!("no plugin named `{}` is loaded", name))
+    }
+
+    // plugins disabled since the last call
+    pub fn take_disabled(&mut self) -> Vec<(Detached, String)>
+    {
+        std::mem::take(&mut self.disabled)
+    }
+
+    // drops every reference to `plugin`. commands it took from other plugins
+    // go back to them, the commands nobody provides anymore are returned
+    fn detach(&mut self, plugin: &SharedPlugin) -> Vec<String>
+    {
+        self.plugins.retain(|other| !Rc::ptr_eq(other, plugin));
+        for owners in self.displaced.values_mut() {
+            owners.retain(|owner| !Rc::ptr_eq(owner, plugin));
+        }
+        let owned = self
+            .commands
+            .iter()
+            .filter(|(_, owner)| Rc::ptr_eq(owner, plugin))
+            .map(|(cmd, _)| cmd.clone())
+            .collect::<Vec<_>>();
+        let mut commands = vec![];
+        for cmd in owned {
+            match self.displaced.get_mut(&cmd).and_then(Vec::pop) {
+                Some(owner) => {
+                    log!("`{}` goes back to `{}`", cmd, owner.lock().unwrap().name());
+                    self.commands.insert(cmd, owner);
+                }
+                None => {
+                    self.commands.remove(&cmd);
+                    commands.push(cmd);
+                }
+            }
         }
+        self.displaced.retain(|_, owners| !owners.is_empty());
+        for subscribers in self.subscriptions.values_mut() {
+            subscribers.retain(|subscriber| !Rc::ptr_eq(subscriber, plugin));
+        }
+        commands
     }
 
-    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) -> Result<(), String>
+    // a plugin that panicked is taken out for good. its state cannot be
+    // trusted anymore, so it is asked for nothing but its bindings
+    fn disable(&mut self, plugin: &SharedPlugin, name: &str, reason: String)
     {
-        let rc_plugin = {
-            let cmds = plugin.commands().into_iter();
-            let rc = Rc::from(Mutex::new(plugin));
-            // TODO: test if all cmds can be added without collision
-            for cmd in cmds {
-                log!("defining cmd {}", cmd);
-                self.register_command(cmd, Rc::clone(&rc))?;
+        log!("disabling plugin {}: {}", name, reason);
+        let commands = self.detach(plugin);
+        let bindings = catch_unwind(AssertUnwindSafe(|| {
+            plugin
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .bindings()
+        }))
+        .unwrap_or_default();
+        let status = self.statuses.iter_mut().find(|status| {
+            status.manifest.name == name && matches!(status.state, PluginState::Loaded)
+        });
+        let source = match status {
+            Some(status) => {
+                status.state = PluginState::Failed(reason.clone());
+                status.source.clone()
             }
-            rc
+            None => String::new(),
         };
-        self.plugins.push(rc_plugin);
-        Ok(())
+        self.disabled.push((
+            Detached {
+                name: name.to_string(),
+                source,
+                commands,
+                bindings,
+            },
+            reason,
+        ));
     }
 
-    fn register_command<T>(&mut self, cmd: T, plugin: SharedPlugin) -> Result<(), String>
+    // runs `call` on the plugin. a panic or a plugin reporting a failure
+    // disables the plugin
+    fn guard<F>(
+        &mut self,
+        plugin: &SharedPlugin,
+        buffer: Option<&mut Buffer>,
+        call: F,
+    ) -> Result<(), String>
     where
-        T: Into<String> + Eq + std::hash::Hash,
-        String: std::borrow::Borrow<T>,
+        F: FnOnce(&mut dyn Plugin, Option<&mut Buffer>) -> PluginResult<()>,
     {
-        if self.commands.contains_key(&cmd) {
-            return Err("command already exists".to_string());
+        let name = plugin.lock().unwrap().name().to_string();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            call(&mut **plugin.lock().unwrap(), buffer)
+        }));
+        let result = match result {
+            Ok(result) => {
+                let failure = plugin.lock().unwrap().failure();
+                if let Some(reason) = failure {
+                    self.disable(plugin, &name, reason);
+                }
+                result
+            }
+            Err(panic) => {
+                let reason = format!("panicked: {}", panic::message(&*panic));
+                self.disable(plugin, &name, reason.clone());
+                Err(reason)
+            }
+        };
+        result.map_err(|err| format!("{}: {}", name, err))
+    }
+
+    // pending requests of all plugins together with the name of the plugin
+    // that made them
+    pub fn requests(&mut self) -> Vec<(String, PluginRequest)>
+    {
+        self.plugins
+            .iter()
+            .flat_map(|plugin| {
+                let mut plugin = plugin.lock().unwrap();
+                let name = plugin.name().to_string();
+                plugin
+                    .requests()
+                    .into_iter()
+                    .map(move |request| (name.clone(), request))
+            })
+            .collect()
+    }
+
+    // unloads every plugin, called when the editor exits
+    pub fn shutdown(&mut self)
+    {
+        self.commands.clear();
+        self.displaced.clear();
+        self.subscriptions.clear();
+        for plugin in self.plugins.drain(..) {
+            let mut plugin = plugin.lock().unwrap();
+            log!("unloading plugin {}", plugin.name());
+            plugin.unload();
+        }
+    }
+
+    pub fn statuses(&self) -> &[PluginStatus]
+    {
+        &self.statuses
+    }
+
+    // commands are registered all or nothing. a command that is already
+    // provided is only taken over if the new plugin has a higher priority
+    fn register_plugin(&mut self, plugin: Box<dyn Plugin>) -> Result<(), String>
+    {
+        let name = plugin.name().to_string();
+        let cmds = plugin.commands();
+
+        let mut conflicts = vec![];
+        for cmd in cmds.iter() {
+            if self.reserved.contains(cmd) && !self.overrides.contains(cmd) {
+                conflicts.push(format!("`{}` is a built-in command", cmd));
+            } else if let Some(owner) = self.commands.get(cmd) {
+                let owner = owner.lock().unwrap().name().to_string();
+                if !self.outranks(&name, &owner) {
+                    conflicts.push(format!("`{}` is already provided by `{}`", cmd, owner));
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts.join(", "));
         }
-        self.commands.insert(cmd.into(), plugin);
+
+        let events = plugin.events();
+        let rc = Rc::from(Mutex::new(plugin));
+        for cmd in cmds {
+            log!("defining cmd {}", cmd);
+            if let Some(owner) = self.commands.insert(cmd.clone(), Rc::clone(&rc)) {
+                log!(
+                    "`{}` takes `{}` from `{}`",
+                    name,
+                    cmd,
+                    owner.lock().unwrap().name()
+                );
+                self.displaced.entry(cmd).or_default().push(owner);
+            }
+        }
+        for event in events {
+            self.subscriptions
+                .entry(event)
+                .or_default()
+                .push(Rc::clone(&rc));
+        }
+        self.plugins.push(rc);
         Ok(())
     }
 
-    pub fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> Result<(), String>
+    // whether `plugin` is listed before `other` in the priority list
+    fn outranks(&self, plugin: &str, other: &str) -> bool
     {
-        if let Some(plugin) = self.commands.get_mut(cmd) {
-            plugin.lock().unwrap().dispatch(buffer, cmd)
+        let rank = |name: &str| self.priority.iter().position(|p| p == name);
+        match (rank(plugin), rank(other)) {
+            (Some(a), Some(b)) => a < b,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn commands(&self) -> Vec<String>
+    {
+        self.commands.keys().cloned().collect()
+    }
+
+    // runs `cmd` if a plugin provides it, `None` otherwise. a failed command
+    // leaves the buffer as it was before. events are not rolled back, copying
+    // the buffer for every one of them would cost too much
+    pub fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> Option<Result<(), String>>
+    {
+        let plugin = Rc::clone(self.commands.get(cmd)?);
+        let snapshot = buffer.snapshot();
+        let result = self.guard(&plugin, Some(buffer), |plugin, buffer| {
+            plugin.dispatch(buffer.unwrap(), cmd)
+        });
+        if result.is_err() {
+            buffer.restore(snapshot);
+        }
+        Some(result)
+    }
+
+    // sends the event to the plugin `name` only, whether it subscribed to it
+    // or not. used for the results of jobs and timers the plugin started
+    pub fn notify_plugin(
+        &mut self,
+        name: &str,
+        buffer: Option<&mut Buffer>,
+        event: &PluginEvent,
+    ) -> Result<(), String>
+    {
+        let plugin = self.find(name)?;
+        self.guard(&plugin, buffer, |plugin, buffer| {
+            plugin.notify(buffer, event)
+        })
+    }
+
+    // notifies every subscriber of the event, errors are collected so that
+    // one failing plugin does not keep the others from being notified
+    pub fn emit(
+        &mut self,
+        mut buffer: Option<&mut Buffer>,
+        event: &PluginEvent,
+    ) -> Result<(), String>
+    {
+        let mut errors = vec![];
+        let plugins = self
+            .subscriptions
+            .get(&event.kind())
+            .cloned()
+            .unwrap_or_default();
+        for plugin in plugins.iter() {
+            let notified = self.guard(plugin, buffer.as_deref_mut(), |plugin, buffer| {
+                plugin.notify(buffer, event)
+            });
+            if let Err(err) = notified {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
         } else {
-            Err("command not found".to_string())
+            Err(errors.join(", "))
+        }
+    }
+}
+
+impl std::fmt::Display for PluginStatus
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error>
+    {
+        let name = if self.manifest.name.is_empty() {
+            "<unknown>"
+        } else {
+            &self.manifest.name
+        };
+        write!(f, "{} {}", name, self.manifest.version)?;
+        if !self.manifest.author.is_empty() {
+            write!(f, " by {}", self.manifest.author)?;
+        }
+        write!(f, " ({})", self.source)?;
+        match &self.state {
+            PluginState::Loaded if self.manifest.description.is_empty() => write!(f, ": loaded"),
+            PluginState::Loaded => write!(f, ": loaded - {}", self.manifest.description),
+            PluginState::Rejected(reason) => write!(f, ": rejected, {}", reason),
+            PluginState::Failed(reason) => write!(f, ": failed, {}", reason),
         }
     }
 }
@@ -1,23 +1,181 @@
 mod dynamic;
+mod event;
+mod host;
 mod manager;
+mod process;
+mod script;
 mod standard;
+mod wasm;
+
+use std::path::Path;
+
+use toml::value::Table;
 
 use crate::buffer::Buffer;
 
 pub use self::dynamic::DynamicPlugin;
-pub use self::manager::CommandManager;
+pub use self::event::{EventKind, PluginEvent};
+pub use self::manager::{CommandManager, Detached, BUILTIN_SOURCE};
+pub use self::process::ProcessPlugin;
+pub use self::script::ScriptPlugin;
 pub use self::standard::StandardPlugin;
+pub use self::wasm::WasmPlugin;
 
 pub type PluginResult<T> = Result<T, String>;
 
+// binds `keys` in `mode` to `command`. `keys` uses the notation of the
+// `map` command
+#[derive(Clone, Debug)]
+pub struct KeyBinding
+{
+    pub mode: String,
+    pub keys: String,
+    pub command: String,
+}
+
+impl KeyBinding
+{
+    // parses the `<mode> <keys> <command>` form used by dynamic plugins
+    pub fn parse(binding: &str) -> Option<Self>
+    {
+        let mut parts = binding.splitn(3, ' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(mode), Some(keys), Some(command)) if !command.trim().is_empty() => Some(Self {
+                mode: mode.to_string(),
+                keys: keys.to_string(),
+                command: command.trim().to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// things plugins ask the editor to do, collected after every plugin call
+#[derive(Clone, Debug)]
+pub enum PluginRequest
+{
+    Message(String),
+    SetMode(String),
+    Map
+    {
+        mode: String,
+        keys: String,
+        command: String,
+    },
+    // decorations, see `crate::decoration`
+    SetStatus
+    {
+        id: String,
+        text: String,
+    },
+    AddSign
+    {
+        row: usize,
+        text: String,
+    },
+    AddVirtualText
+    {
+        row: usize,
+        text: String,
+    },
+    AddHighlight
+    {
+        row: usize,
+        start: usize,
+        end: usize,
+        group: String,
+    },
+    ClearDecorations,
+    // background work, see `crate::job`. `interval` is in milliseconds
+    StartJob
+    {
+        id: String,
+        command: String,
+        args: Vec<String>,
+    },
+    StopJob(String),
+    StartTimer
+    {
+        id: String,
+        interval: u64,
+        repeat: bool,
+    },
+    StopTimer(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PluginManifest
+{
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    // minimum editor version the plugin needs
+    pub editor_version: Option<String>,
+}
+
 pub trait Plugin
 {
-    fn name(&self) -> &'static str;
+    fn name(&self) -> &str;
+    fn manifest(&self) -> PluginManifest
+    {
+        PluginManifest {
+            name: self.name().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..PluginManifest::default()
+        }
+    }
     fn commands(&self) -> Vec<String>;
     fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>;
-    fn unload(mut self)
-    where
-        Self: Sized,
+    // modes the plugin adds next to view, insert and command
+    fn modes(&self) -> Vec<String>
+    {
+        vec![]
+    }
+    // default bindings, applied unless the user bound the same keys
+    fn bindings(&self) -> Vec<KeyBinding>
+    {
+        vec![]
+    }
+    // events the plugin wants to be notified about
+    fn events(&self) -> Vec<EventKind>
+    {
+        vec![]
+    }
+    fn notify(&mut self, _buffer: Option<&mut Buffer>, _event: &PluginEvent) -> PluginResult<()>
     {
+        Ok(())
+    }
+    // receives the `[plugin.<name>]` table of the configuration right after
+    // loading
+    fn configure(&mut self, _config: &Table) -> PluginResult<()>
+    {
+        Ok(())
+    }
+    fn requests(&mut self) -> Vec<PluginRequest>
+    {
+        vec![]
+    }
+    // why the plugin cannot be used anymore, e.g. because it stopped
+    // answering. the editor disables it once this is set
+    fn failure(&self) -> Option<String>
+    {
+        None
+    }
+    // called once before the plugin is dropped, either on exit or when it is
+    // unloaded at runtime
+    fn unload(&mut self) {}
+}
+
+// file name without extension and `lib` prefix, e.g. `greeter` for
+// `libgreeter.so`
+pub fn name_from_path(path: &Path) -> String
+{
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    match stem.strip_prefix("lib") {
+        Some(name) => name.to_string(),
+        None => stem,
     }
 }
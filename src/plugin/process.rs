@@ -0,0 +1,464 @@
+// plugins running as separate processes, talking json-rpc 2.0 over the
+// stdin and stdout of the child. the protocol is described in
+// `doc/plugin-protocol.md`
+
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use toml::value::Table;
+
+use crate::buffer::Buffer;
+use crate::config::ProcessConfig;
+use crate::input::{CursorMove, Event};
+use crate::plugin::{
+    EventKind, KeyBinding, Plugin, PluginEvent, PluginManifest, PluginRequest, PluginResult,
+};
+
+pub const PROTOCOL_VERSION: u64 = 1;
+
+pub struct ProcessPlugin
+{
+    manifest: PluginManifest,
+    commands: Vec<String>,
+    events: Vec<EventKind>,
+    modes: Vec<String>,
+    bindings: Vec<KeyBinding>,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    // every line the child writes, parsed by the reader thread
+    incoming: Receiver<Value>,
+    requests: Vec<PluginRequest>,
+    next_id: u64,
+    timeout: Duration,
+    // set once the plugin stopped reading or answering in time
+    failure: Option<String>,
+}
+
+impl ProcessPlugin
+{
+    // `events` is the channel of the app, it is woken for every notification
+    // so they are applied without waiting for the next key
+    pub fn spawn(config: &ProcessConfig, events: Sender<Event>) -> PluginResult<Box<Self>>
+    {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("could not start `{}`: {}", config.command, err))?;
+
+        let stdin = child.stdin.take();
+        if let Some(stdin) = &stdin {
+            unsafe {
+                let fd = stdin.as_raw_fd();
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+        }
+        let stdout = child.stdout.take().unwrap();
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match serde_json::from_str::<Value>(&line) {
+                    Ok(message) => {
+                        let notification = message.get("method").is_some();
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                        if notification {
+                            events.send(Event::PluginRequest).ok();
+                        }
+                    }
+                    Err(err) => log!("invalid message from plugin: {}", err),
+                }
+            }
+        });
+
+        let mut plugin = Box::new(Self {
+            manifest: PluginManifest {
+                name: config.name.clone(),
+                ..PluginManifest::default()
+            },
+            commands: vec![],
+            events: vec![],
+            modes: vec![],
+            bindings: vec![],
+            child,
+            stdin,
+            incoming,
+            requests: vec![],
+            next_id: 0,
+            timeout: Duration::from_millis(config.timeout),
+            failure: None,
+        });
+
+        let result = plugin.call(
+            "initialize",
+            json!({
+                "protocol_version": PROTOCOL_VERSION,
+                "editor_version": env!("CARGO_PKG_VERSION"),
+            }),
+        )?;
+        if result["protocol_version"].as_u64() != Some(PROTOCOL_VERSION) {
+            return Err(format!(
+                "protocol version {} is not supported, expected {}",
+                result["protocol_version"], PROTOCOL_VERSION
+            ));
+        }
+        let string = |key: &str| result[key].as_str().unwrap_or_default().to_string();
+        plugin.manifest.version = string("version");
+        plugin.manifest.author = string("author");
+        plugin.manifest.description = string("description");
+        plugin.commands = strings(&result["commands"]);
+        plugin.events = strings(&result["events"])
+            .iter()
+            .filter_map(|name| EventKind::from_name(name))
+            .collect();
+        plugin.modes = strings(&result["modes"]);
+        plugin.bindings = result["bindings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|binding| {
+                let field = |key: &str| binding[key].as_str().map(str::to_string);
+                Some(KeyBinding {
+                    mode: field("mode")?,
+                    keys: field("keys")?,
+                    command: field("command")?,
+                })
+            })
+            .collect();
+
+        Ok(plugin)
+    }
+
+    // the input of the child is non-blocking, a plugin that does not read it
+    // within the timeout fails
+    fn send(&mut self, message: Value) -> PluginResult<()>
+    {
+        let stdin = self
+            .stdin
+            .as_ref()
+            .ok_or_else(|| "plugin is shut down".to_string())?;
+        let line = format!("{}\n", message);
+        match write_all(stdin.as_raw_fd(), line.as_bytes(), self.timeout) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => Err(self.fail(format!(
+                "did not read its input within {}ms",
+                self.timeout.as_millis()
+            ))),
+            Err(err) => Err(self.fail(format!("is gone: {}", err))),
+        }
+    }
+
+    // a message only half written or an answer still pending leaves the
+    // connection in an unknown state, so the plugin is not used after this
+    fn fail(&mut self, reason: String) -> String
+    {
+        let message = format!("plugin `{}` {}", self.manifest.name, reason);
+        self.failure = Some(reason);
+        message
+    }
+
+    fn notification(&mut self, method: &str, params: Value) -> PluginResult<()>
+    {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    // sends a request and waits for the matching response. notifications
+    // arriving in the meantime are queued
+    fn call(&mut self, method: &str, params: Value) -> PluginResult<Value>
+    {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let message = match self.incoming.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(self.fail(format!(
+                        "did not answer `{}` within {}ms",
+                        method,
+                        self.timeout.as_millis()
+                    )))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(self.fail("exited".to_string())),
+            };
+
+            if message.get("method").is_some() {
+                self.receive(message);
+                continue;
+            }
+            // answers to other requests are dropped
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(error["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string());
+            }
+            return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+
+    // handles a notification sent by the plugin. rows and columns have to
+    // be integers of at least zero, notifications with others are dropped
+    fn receive(&mut self, message: Value)
+    {
+        let params = &message["params"];
+        let string = |key: &str| params[key].as_str().unwrap_or_default().to_string();
+        let number = |key: &str| params[key].as_u64().map(|n| n as usize);
+        let request = match message["method"].as_str() {
+            Some("message") => Some(PluginRequest::Message(string("text"))),
+            Some("log") => {
+                log!("{}: {}", self.manifest.name, string("text"));
+                return;
+            }
+            Some("status") => Some(PluginRequest::SetStatus {
+                id: string("id"),
+                text: string("text"),
+            }),
+            Some("sign") => number("row").map(|row| PluginRequest::AddSign {
+                row,
+                text: string("text"),
+            }),
+            Some("virtual_text") => number("row").map(|row| PluginRequest::AddVirtualText {
+                row,
+                text: string("text"),
+            }),
+            Some("highlight") => match (number("row"), number("start"), number("end")) {
+                (Some(row), Some(start), Some(end)) if start <= end => {
+                    Some(PluginRequest::AddHighlight {
+                        row,
+                        start,
+                        end,
+                        group: string("group"),
+                    })
+                }
+                _ => None,
+            },
+            Some("clear") => Some(PluginRequest::ClearDecorations),
+            Some("start_job") => Some(PluginRequest::StartJob {
+                id: string("id"),
+                command: string("command"),
+                args: strings(&params["args"]),
+            }),
+            Some("stop_job") => Some(PluginRequest::StopJob(string("id"))),
+            Some("start_timer") => Some(PluginRequest::StartTimer {
+                id: string("id"),
+                interval: params["interval"].as_u64().unwrap_or_default(),
+                repeat: params["repeat"].as_bool().unwrap_or_default(),
+            }),
+            Some("stop_timer") => Some(PluginRequest::StopTimer(string("id"))),
+            method => {
+                log!("{}: unknown notification {:?}", self.manifest.name, method);
+                return;
+            }
+        };
+        match request {
+            Some(request) => self.requests.push(request),
+            None => log!("{}: invalid notification {}", self.manifest.name, message),
+        }
+    }
+}
+
+impl Plugin for ProcessPlugin
+{
+    fn name(&self) -> &str
+    {
+        &self.manifest.name
+    }
+
+    fn manifest(&self) -> PluginManifest
+    {
+        self.manifest.clone()
+    }
+
+    fn commands(&self) -> Vec<String>
+    {
+        self.commands.clone()
+    }
+
+    fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>
+    {
+        let result = self.call(
+            "dispatch",
+            json!({ "command": cmd, "buffer": snapshot(buffer) }),
+        )?;
+        apply(buffer, &result)
+    }
+
+    fn modes(&self) -> Vec<String>
+    {
+        self.modes.clone()
+    }
+
+    fn bindings(&self) -> Vec<KeyBinding>
+    {
+        self.bindings.clone()
+    }
+
+    fn events(&self) -> Vec<EventKind>
+    {
+        self.events.clone()
+    }
+
+    // events are notifications, the plugin cannot edit the buffer from here
+    fn notify(&mut self, _buffer: Option<&mut Buffer>, event: &PluginEvent) -> PluginResult<()>
+    {
+        self.notification(
+            "notify",
+            json!({ "event": event.kind().name(), "payload": event.payload() }),
+        )
+    }
+
+    fn configure(&mut self, config: &Table) -> PluginResult<()>
+    {
+        let config = serde_json::to_value(config).map_err(|err| err.to_string())?;
+        self.call("configure", json!({ "config": config }))
+            .map(|_| ())
+    }
+
+    fn failure(&self) -> Option<String>
+    {
+        self.failure.clone()
+    }
+
+    fn requests(&mut self) -> Vec<PluginRequest>
+    {
+        while let Ok(message) = self.incoming.try_recv() {
+            if message.get("method").is_some() {
+                self.receive(message);
+            }
+        }
+        std::mem::take(&mut self.requests)
+    }
+
+    // asks the plugin to exit and kills it if it does not comply in time
+    fn unload(&mut self)
+    {
+        if self.stdin.is_none() {
+            return;
+        }
+        if self.failure.is_none() {
+            self.notification("shutdown", Value::Null).ok();
+        }
+        self.stdin = None;
+
+        let deadline = Instant::now() + self.timeout;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+impl Drop for ProcessPlugin
+{
+    fn drop(&mut self)
+    {
+        self.unload();
+    }
+}
+
+// writes all of `bytes` to the non-blocking `fd`, waiting at most `timeout`
+// for it to become writable
+fn write_all(fd: RawFd, mut bytes: &[u8], timeout: Duration) -> io::Result<()>
+{
+    let deadline = Instant::now() + timeout;
+    while !bytes.is_empty() {
+        let written =
+            unsafe { libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+        if written >= 0 {
+            bytes = &bytes[written as usize..];
+            continue;
+        }
+        let err = io::Error::last_os_error();
+        match err.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => {}
+            _ => return Err(err),
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_millis(0) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"));
+        }
+        let mut polled = libc::pollfd {
+            fd,
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut polled, 1, left.as_millis() as libc::c_int) };
+    }
+    Ok(())
+}
+
+fn strings(value: &Value) -> Vec<String>
+{
+    value.as_array().map_or_else(Vec::new, |items| {
+        items
+            .iter()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect()
+    })
+}
+
+fn snapshot(buffer: &Buffer) -> Value
+{
+    let (col, row) = buffer.get_cursor();
+    json!({
+        "path": buffer.source_path().as_ref().map(|path| path.display().to_string()),
+        "lines": buffer.lines(),
+        "cursor": [col, row],
+    })
+}
+
+// applies the result of a dispatch: either all `lines` are replaced or the
+// `edits` are executed in order
+fn apply(buffer: &mut Buffer, result: &Value) -> PluginResult<()>
+{
+    if let Some(lines) = result.get("lines") {
+        buffer.set_lines(strings(lines));
+    }
+
+    for edit in result["edits"].as_array().into_iter().flatten() {
+        let row = edit["row"]
+            .as_u64()
+            .ok_or_else(|| format!("edit without row: {}", edit))? as usize;
+        let text = || edit["text"].as_str().unwrap_or_default().to_string();
+        let done = match edit["op"].as_str() {
+            Some("set") => buffer.set_line(row, text()),
+            Some("insert") => buffer.insert_line(row, text()),
+            Some("remove") => buffer.remove_line(row),
+            _ => return Err(format!("unknown edit: {}", edit)),
+        };
+        if !done {
+            return Err(format!("edit out of range: {}", edit));
+        }
+    }
+
+    if let (Some(col), Some(row)) = (result["cursor"][0].as_i64(), result["cursor"][1].as_i64()) {
+        buffer.move_cursor(CursorMove::Absolute(col, row));
+    }
+    buffer.clamp_cursor();
+    Ok(())
+}
@@ -0,0 +1,424 @@
+// user commands written in rhai. the init script runs once at load time and
+// registers commands, key mappings and hooks through the functions defined in
+// `ScriptPlugin::load`:
+//
+//   command("greet", |buffer| buffer.set_line(0, "hello"));
+//   map("view", "<C-g>", "greet");
+//   define_mode("window"); map("window", "q", "mode view");
+//   on("after-save", |buffer, path| message(`saved ${path}`));
+//   add_sign(0, "!"); set_status("clock", "12:00");
+//   start_job("lint", "cargo", ["check"]); start_timer("tick", 1000, true);
+//
+// scripts work on a snapshot of the buffer which is written back once the
+// call returns
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, AST};
+
+use crate::buffer::Buffer;
+use crate::input::CursorMove;
+use crate::plugin::{
+    name_from_path, EventKind, Plugin, PluginEvent, PluginManifest, PluginRequest, PluginResult,
+};
+
+// keeps endless loops in scripts from freezing the editor
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+#[derive(Default)]
+struct ScriptState
+{
+    commands: HashMap<String, FnPtr>,
+    hooks: HashMap<EventKind, Vec<FnPtr>>,
+    modes: Vec<String>,
+    requests: Vec<PluginRequest>,
+}
+
+struct Snapshot
+{
+    lines: Vec<String>,
+    cursor: (i64, i64),
+    path: String,
+    changed: bool,
+}
+
+// the `Buffer` type scripts see
+#[derive(Clone)]
+struct ScriptBuffer(Rc<RefCell<Snapshot>>);
+
+pub struct ScriptPlugin
+{
+    name: String,
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<ScriptState>>,
+}
+
+impl ScriptPlugin
+{
+    pub fn load(path: &Path) -> PluginResult<Box<Self>>
+    {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| format!("could not be read: {}", err))?;
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| log!("script: {}", text));
+        engine.on_debug(|text, _, pos| log!("script {}: {}", pos, text));
+
+        let commands = Rc::clone(&state);
+        engine.register_fn("command", move |name: &str, f: FnPtr| {
+            commands.borrow_mut().commands.insert(name.to_string(), f);
+        });
+        let hooks = Rc::clone(&state);
+        engine.register_fn(
+            "on",
+            move |event: &str, f: FnPtr| -> Result<(), Box<EvalAltResult>> {
+                let kind = EventKind::from_name(event)
+                    .ok_or_else(|| format!("unknown event `{}`", event))?;
+                hooks
+                    .borrow_mut()
+                    .hooks
+                    .entry(kind)
+                    .or_insert_with(Vec::new)
+                    .push(f);
+                Ok(())
+            },
+        );
+        let modes = Rc::clone(&state);
+        engine.register_fn("define_mode", move |mode: &str| {
+            modes.borrow_mut().modes.push(mode.to_string());
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("map", move |mode: &str, keys: &str, command: &str| {
+            requests.borrow_mut().requests.push(PluginRequest::Map {
+                mode: mode.to_string(),
+                keys: keys.to_string(),
+                command: command.to_string(),
+            });
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("message", move |text: &str| {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::Message(text.to_string()));
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("set_mode", move |mode: &str| {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::SetMode(mode.to_string()));
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("set_status", move |id: &str, text: &str| {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::SetStatus {
+                    id: id.to_string(),
+                    text: text.to_string(),
+                });
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("add_sign", move |row: i64, text: &str| {
+            if let Some(row) = index(row) {
+                requests.borrow_mut().requests.push(PluginRequest::AddSign {
+                    row,
+                    text: text.to_string(),
+                });
+            }
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("add_virtual_text", move |row: i64, text: &str| {
+            if let Some(row) = index(row) {
+                requests
+                    .borrow_mut()
+                    .requests
+                    .push(PluginRequest::AddVirtualText {
+                        row,
+                        text: text.to_string(),
+                    });
+            }
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn(
+            "add_highlight",
+            move |row: i64, start: i64, end: i64, group: &str| {
+                if let (Some(row), Some(start), Some(end)) = (index(row), index(start), index(end))
+                {
+                    requests
+                        .borrow_mut()
+                        .requests
+                        .push(PluginRequest::AddHighlight {
+                            row,
+                            start,
+                            end,
+                            group: group.to_string(),
+                        });
+                }
+            },
+        );
+        let requests = Rc::clone(&state);
+        engine.register_fn("start_job", move |id: &str, command: &str, args: Array| {
+            let args = args.into_iter().map(|arg| arg.to_string()).collect();
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::StartJob {
+                    id: id.to_string(),
+                    command: command.to_string(),
+                    args,
+                });
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("stop_job", move |id: &str| {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::StopJob(id.to_string()));
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn(
+            "start_timer",
+            move |id: &str, interval: i64, repeat: bool| {
+                requests
+                    .borrow_mut()
+                    .requests
+                    .push(PluginRequest::StartTimer {
+                        id: id.to_string(),
+                        interval: interval.max(0) as u64,
+                        repeat,
+                    });
+            },
+        );
+        let requests = Rc::clone(&state);
+        engine.register_fn("stop_timer", move |id: &str| {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::StopTimer(id.to_string()));
+        });
+        let requests = Rc::clone(&state);
+        engine.register_fn("clear_decorations", move || {
+            requests
+                .borrow_mut()
+                .requests
+                .push(PluginRequest::ClearDecorations);
+        });
+
+        engine
+            .register_type_with_name::<ScriptBuffer>("Buffer")
+            .register_fn("line_count", |buffer: &mut ScriptBuffer| {
+                buffer.0.borrow().lines.len() as i64
+            })
+            .register_fn("line", |buffer: &mut ScriptBuffer, row: i64| {
+                let snapshot = buffer.0.borrow();
+                index(row)
+                    .and_then(|row| snapshot.lines.get(row))
+                    .map_or(Dynamic::UNIT, |line| line.clone().into())
+            })
+            .register_fn(
+                "set_line",
+                |buffer: &mut ScriptBuffer, row: i64, text: &str| {
+                    buffer.edit(
+                        |lines| match index(row).and_then(|row| lines.get_mut(row)) {
+                            Some(line) => {
+                                *line = text.to_string();
+                                true
+                            }
+                            None => false,
+                        },
+                    )
+                },
+            )
+            .register_fn(
+                "insert_line",
+                |buffer: &mut ScriptBuffer, row: i64, text: &str| {
+                    buffer.edit(|lines| match index(row).filter(|row| *row <= lines.len()) {
+                        Some(row) => {
+                            lines.insert(row, text.to_string());
+                            true
+                        }
+                        None => false,
+                    })
+                },
+            )
+            .register_fn("remove_line", |buffer: &mut ScriptBuffer, row: i64| {
+                buffer.edit(|lines| match index(row).filter(|row| *row < lines.len()) {
+                    Some(row) => {
+                        lines.remove(row);
+                        true
+                    }
+                    None => false,
+                })
+            })
+            .register_fn("cursor", |buffer: &mut ScriptBuffer| {
+                let (col, row) = buffer.0.borrow().cursor;
+                vec![Dynamic::from(col), Dynamic::from(row)] as Array
+            })
+            .register_fn(
+                "set_cursor",
+                |buffer: &mut ScriptBuffer, col: i64, row: i64| {
+                    buffer.0.borrow_mut().cursor = (col, row);
+                },
+            )
+            .register_fn("path", |buffer: &mut ScriptBuffer| {
+                buffer.0.borrow().path.clone()
+            });
+
+        let ast = engine
+            .compile(&source)
+            .map_err(|err| format!("invalid script: {}", err))?;
+        engine
+            .run_ast(&ast)
+            .map_err(|err| format!("script failed: {}", err))?;
+
+        Ok(Box::new(Self {
+            name: name_from_path(path),
+            engine,
+            ast,
+            state,
+        }))
+    }
+
+    pub fn is_script(path: &Path) -> bool
+    {
+        path.is_file() && path.extension().is_some_and(|ext| ext == "rhai")
+    }
+
+    // runs `f` with a snapshot of `buffer` and writes the changes back
+    fn call(
+        &self,
+        f: &FnPtr,
+        buffer: Option<&mut Buffer>,
+        payload: Option<String>,
+    ) -> PluginResult<()>
+    {
+        let snapshot = buffer.as_ref().map(|buffer| ScriptBuffer::new(buffer));
+        let argument = snapshot.clone().map_or(Dynamic::UNIT, Dynamic::from);
+        let result = match payload {
+            Some(payload) => f.call::<Dynamic>(&self.engine, &self.ast, (argument, payload)),
+            None => f.call::<Dynamic>(&self.engine, &self.ast, (argument,)),
+        };
+        if let (Some(buffer), Some(snapshot)) = (buffer, snapshot) {
+            snapshot.apply(buffer);
+        }
+        result
+            .map(|_| ())
+            .map_err(|err| format!("script `{}` failed: {}", self.name, err))
+    }
+}
+
+impl Plugin for ScriptPlugin
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn manifest(&self) -> PluginManifest
+    {
+        PluginManifest {
+            name: self.name.clone(),
+            description: "rhai script".to_string(),
+            ..PluginManifest::default()
+        }
+    }
+
+    fn commands(&self) -> Vec<String>
+    {
+        self.state.borrow().commands.keys().cloned().collect()
+    }
+
+    fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>
+    {
+        let f = self.state.borrow().commands.get(cmd).cloned();
+        match f {
+            Some(f) => self.call(&f, Some(buffer), None),
+            None => Err(format!("no command `{}` in script", cmd)),
+        }
+    }
+
+    fn modes(&self) -> Vec<String>
+    {
+        self.state.borrow().modes.clone()
+    }
+
+    fn events(&self) -> Vec<EventKind>
+    {
+        self.state.borrow().hooks.keys().cloned().collect()
+    }
+
+    fn notify(&mut self, mut buffer: Option<&mut Buffer>, event: &PluginEvent) -> PluginResult<()>
+    {
+        let hooks = self
+            .state
+            .borrow()
+            .hooks
+            .get(&event.kind())
+            .cloned()
+            .unwrap_or_default();
+        for hook in hooks.iter() {
+            self.call(hook, buffer.as_deref_mut(), Some(event.payload()))?;
+        }
+        Ok(())
+    }
+
+    fn requests(&mut self) -> Vec<PluginRequest>
+    {
+        std::mem::take(&mut self.state.borrow_mut().requests)
+    }
+}
+
+impl ScriptBuffer
+{
+    fn new(buffer: &Buffer) -> Self
+    {
+        Self(Rc::new(RefCell::new(Snapshot {
+            lines: buffer.lines().to_vec(),
+            cursor: buffer.get_cursor(),
+            path: buffer
+                .source_path()
+                .as_ref()
+                .map_or_else(String::new, |path| path.display().to_string()),
+            changed: false,
+        })))
+    }
+
+    fn edit<F>(&mut self, f: F) -> bool
+    where
+        F: FnOnce(&mut Vec<String>) -> bool,
+    {
+        let mut snapshot = self.0.borrow_mut();
+        let done = f(&mut snapshot.lines);
+        snapshot.changed |= done;
+        done
+    }
+
+    fn apply(&self, buffer: &mut Buffer)
+    {
+        let snapshot = self.0.borrow();
+        if snapshot.changed {
+            buffer.set_lines(snapshot.lines.clone());
+        }
+        if snapshot.cursor != buffer.get_cursor() {
+            let (col, row) = snapshot.cursor;
+            buffer.move_cursor(CursorMove::Absolute(col, row));
+        }
+    }
+}
+
+fn index(row: i64) -> Option<usize>
+{
+    if row < 0 {
+        None
+    } else {
+        Some(row as usize)
+    }
+}
@@ -13,7 +13,7 @@ impl StandardPlugin
 
 impl Plugin for StandardPlugin
 {
-    fn name(&self) -> &'static str
+    fn name(&self) -> &str
     {
         // TODO: lookup global variable in plugin?
         "StandardPlugin"
@@ -46,8 +46,4 @@ impl Plugin for StandardPlugin
         }
         Ok(())
     }
-
-    fn unload(mut self)
-    {
-    }
 }
@@ -0,0 +1,607 @@
+// plugins compiled to webassembly, running inside a wasmi sandbox. a
+// module has to export
+//
+//   memory
+//   loe_alloc(len: i32) -> i32          memory for strings passed in, owned
+//                                       by the module afterwards
+//   loe_dispatch(ptr: i32, len: i32) -> i32
+//
+// and may export `loe_init()`, `loe_configure(ptr, len) -> i32` and
+// `loe_notify(event_ptr, event_len, payload_ptr, payload_len) -> i32`.
+// functions returning i32 report success with 0. the editor provides the
+// imports of module `loe` defined in `define_host_api`. commands, events,
+// modes and key bindings are registered from `loe_init` with
+// `register_command`, `subscribe`, `define_mode` and `bind_key`, decorations
+// are added with imports like `add_sign(row, ptr, len)` and background jobs
+// with `start_job(id_ptr, id_len, argv_ptr, argv_len)`. every call into the
+// module gets a fixed amount of fuel and is aborted once it is used up
+
+use std::path::Path;
+use std::ptr;
+
+use toml::value::{Table, Value};
+use wasmi::{Caller, Engine, Extern, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::buffer::Buffer;
+use crate::input::CursorMove;
+use crate::plugin::{
+    name_from_path, EventKind, KeyBinding, Plugin, PluginEvent, PluginManifest, PluginRequest,
+    PluginResult,
+};
+
+// roughly the number of instructions a single call may execute, can be
+// changed per plugin with `fuel` in its `[plugin.<name>]` table
+const DEFAULT_FUEL: u64 = 10_000_000;
+
+// the longest string a module may pass to the editor, in bytes
+const MAX_STRING_LEN: usize = 1 << 20;
+
+struct HostState
+{
+    // only set for the duration of a call that has access to the buffer
+    buffer: *mut Buffer,
+    commands: Vec<String>,
+    events: Vec<EventKind>,
+    modes: Vec<String>,
+    bindings: Vec<KeyBinding>,
+    requests: Vec<PluginRequest>,
+}
+
+pub struct WasmPlugin
+{
+    name: String,
+    fuel: u64,
+    store: Store<HostState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    dispatch: TypedFunc<(i32, i32), i32>,
+    configure: Option<TypedFunc<(i32, i32), i32>>,
+    notify: Option<TypedFunc<(i32, i32, i32, i32), i32>>,
+}
+
+impl WasmPlugin
+{
+    pub fn load(path: &Path) -> PluginResult<Box<Self>>
+    {
+        let bytes = std::fs::read(path).map_err(|err| format!("could not be read: {}", err))?;
+
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes[..])
+            .map_err(|err| format!("invalid wasm module: {}", err))?;
+
+        let mut store = Store::new(
+            &engine,
+            HostState {
+                buffer: ptr::null_mut(),
+                commands: vec![],
+                events: vec![],
+                modes: vec![],
+                bindings: vec![],
+                requests: vec![],
+            },
+        );
+        store
+            .add_fuel(DEFAULT_FUEL)
+            .map_err(|err| err.to_string())?;
+
+        let mut linker = Linker::new(&engine);
+        define_host_api(&mut linker).map_err(|err| err.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|err| format!("could not be instantiated: {}", err))?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| "missing required export `memory`".to_string())?;
+        let required = |err: wasmi::Error| format!("missing required export: {}", err);
+        let alloc = instance
+            .get_typed_func(&store, "loe_alloc")
+            .map_err(required)?;
+        let dispatch = instance
+            .get_typed_func(&store, "loe_dispatch")
+            .map_err(required)?;
+        let init = instance.get_typed_func::<(), ()>(&store, "loe_init").ok();
+
+        let mut plugin = Self {
+            name: name_from_path(path),
+            fuel: DEFAULT_FUEL,
+            configure: instance.get_typed_func(&store, "loe_configure").ok(),
+            notify: instance.get_typed_func(&store, "loe_notify").ok(),
+            store,
+            memory,
+            alloc,
+            dispatch,
+        };
+        if let Some(init) = init {
+            plugin.run(None, |store| Ok(init.call(store, ())?))?;
+        }
+        Ok(Box::new(plugin))
+    }
+
+    pub fn is_module(path: &Path) -> bool
+    {
+        path.is_file() && path.extension().is_some_and(|ext| ext == "wasm")
+    }
+
+    // refuels the store and makes `buffer` available to the host functions
+    // while `call` runs
+    fn run<R, F>(&mut self, buffer: Option<&mut Buffer>, call: F) -> PluginResult<R>
+    where
+        F: FnOnce(&mut Store<HostState>) -> Result<R, wasmi::Error>,
+    {
+        let remaining = self.store.consume_fuel(0).map_err(|err| err.to_string())?;
+        if remaining < self.fuel {
+            self.store.add_fuel(self.fuel - remaining)
+        } else {
+            self.store.consume_fuel(remaining - self.fuel).map(|_| ())
+        }
+        .map_err(|err| err.to_string())?;
+
+        self.store.data_mut().buffer = buffer.map_or(ptr::null_mut(), |buffer| buffer);
+        let result = call(&mut self.store);
+        self.store.data_mut().buffer = ptr::null_mut();
+        result.map_err(|err| format!("plugin `{}` trapped: {}", self.name, err))
+    }
+
+    fn check(&self, status: i32) -> PluginResult<()>
+    {
+        match status {
+            0 => Ok(()),
+            _ => Err(format!(
+                "plugin `{}` failed with status {}",
+                self.name, status
+            )),
+        }
+    }
+}
+
+impl Plugin for WasmPlugin
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn manifest(&self) -> PluginManifest
+    {
+        PluginManifest {
+            name: self.name.clone(),
+            description: "wasm module".to_string(),
+            ..PluginManifest::default()
+        }
+    }
+
+    fn commands(&self) -> Vec<String>
+    {
+        self.store.data().commands.clone()
+    }
+
+    fn dispatch(&mut self, buffer: &mut Buffer, cmd: &str) -> PluginResult<()>
+    {
+        let (alloc, memory, dispatch) = (self.alloc, self.memory, self.dispatch);
+        let status = self.run(Some(buffer), |store| {
+            let (ptr, len) = pass(store, &alloc, memory, cmd)?;
+            Ok(dispatch.call(store, (ptr, len))?)
+        })?;
+        self.check(status)
+    }
+
+    fn modes(&self) -> Vec<String>
+    {
+        self.store.data().modes.clone()
+    }
+
+    fn bindings(&self) -> Vec<KeyBinding>
+    {
+        self.store.data().bindings.clone()
+    }
+
+    fn events(&self) -> Vec<EventKind>
+    {
+        self.store.data().events.clone()
+    }
+
+    fn notify(&mut self, buffer: Option<&mut Buffer>, event: &PluginEvent) -> PluginResult<()>
+    {
+        let notify = match self.notify {
+            Some(notify) => notify,
+            None => return Ok(()),
+        };
+        let (alloc, memory) = (self.alloc, self.memory);
+        let status = self.run(buffer, |store| {
+            let (event_ptr, event_len) = pass(store, &alloc, memory, event.kind().name())?;
+            let (payload_ptr, payload_len) = pass(store, &alloc, memory, &event.payload())?;
+            Ok(notify.call(store, (event_ptr, event_len, payload_ptr, payload_len))?)
+        })?;
+        self.check(status)
+    }
+
+    fn configure(&mut self, config: &Table) -> PluginResult<()>
+    {
+        if let Some(fuel) = config.get("fuel").and_then(Value::as_integer) {
+            self.fuel = fuel.max(0) as u64;
+        }
+        let configure = match self.configure {
+            Some(configure) => configure,
+            None => return Ok(()),
+        };
+        let config = toml::to_string(config).map_err(|err| err.to_string())?;
+        let (alloc, memory) = (self.alloc, self.memory);
+        let status = self.run(None, |store| {
+            let (ptr, len) = pass(store, &alloc, memory, &config)?;
+            Ok(configure.call(store, (ptr, len))?)
+        })?;
+        self.check(status)
+    }
+
+    fn requests(&mut self) -> Vec<PluginRequest>
+    {
+        std::mem::take(&mut self.store.data_mut().requests)
+    }
+}
+
+// copies `s` into memory allocated by the module
+fn pass(
+    store: &mut Store<HostState>,
+    alloc: &TypedFunc<i32, i32>,
+    memory: Memory,
+    s: &str,
+) -> Result<(i32, i32), wasmi::Error>
+{
+    let len = s.len() as i32;
+    let ptr = alloc.call(&mut *store, len)?;
+    memory.write(&mut *store, ptr as usize, s.as_bytes())?;
+    Ok((ptr, len))
+}
+
+fn define_host_api(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error>
+{
+    linker.func_wrap("loe", "line_count", |caller: Caller<'_, HostState>| {
+        with_buffer(&caller, |buffer| buffer.content_len() as i32).unwrap_or(-1)
+    })?;
+    // copies at most `cap` bytes of the line to `ptr` and returns the length
+    // of the whole line, -1 if it does not exist
+    linker.func_wrap(
+        "loe",
+        "get_line",
+        |mut caller: Caller<'_, HostState>, row: i32, ptr: i32, cap: i32| {
+            let line = with_buffer(&caller, |buffer| {
+                row_index(row).and_then(|row| buffer.line(row).map(str::to_string))
+            });
+            match line.and_then(|line| line) {
+                Some(line) => {
+                    let len = line.len().min(cap.max(0) as usize);
+                    if write_bytes(&mut caller, ptr, &line.as_bytes()[..len]) {
+                        line.len() as i32
+                    } else {
+                        -1
+                    }
+                }
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "set_line",
+        |caller: Caller<'_, HostState>, row: i32, ptr: i32, len: i32| {
+            edit_line(&caller, row, ptr, len, Buffer::set_line)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "insert_line",
+        |caller: Caller<'_, HostState>, row: i32, ptr: i32, len: i32| {
+            edit_line(&caller, row, ptr, len, Buffer::insert_line)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "remove_line",
+        |caller: Caller<'_, HostState>, row: i32| {
+            let removed = with_buffer(&caller, |buffer| {
+                row_index(row).is_some_and(|row| buffer.remove_line(row))
+            });
+            status(removed == Some(true))
+        },
+    )?;
+    linker.func_wrap("loe", "cursor_col", |caller: Caller<'_, HostState>| {
+        with_buffer(&caller, |buffer| buffer.get_cursor().0 as i32).unwrap_or(-1)
+    })?;
+    linker.func_wrap("loe", "cursor_row", |caller: Caller<'_, HostState>| {
+        with_buffer(&caller, |buffer| buffer.get_cursor().1 as i32).unwrap_or(-1)
+    })?;
+    linker.func_wrap(
+        "loe",
+        "set_cursor",
+        |caller: Caller<'_, HostState>, col: i32, row: i32| {
+            let moved = with_buffer(&caller, |buffer| {
+                buffer.move_cursor(CursorMove::Absolute(col as i64, row as i64))
+            });
+            status(moved.is_some())
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "register_command",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| match read_string(&caller, ptr, len)
+        {
+            Some(command) => {
+                caller.data_mut().commands.push(command);
+                0
+            }
+            None => -1,
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "subscribe",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| match read_string(&caller, ptr, len)
+            .and_then(|name| EventKind::from_name(&name))
+        {
+            Some(event) => {
+                caller.data_mut().events.push(event);
+                0
+            }
+            None => -1,
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "define_mode",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| match read_string(&caller, ptr, len)
+        {
+            Some(mode) => {
+                caller.data_mut().modes.push(mode);
+                0
+            }
+            None => -1,
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "bind_key",
+        |mut caller: Caller<'_, HostState>,
+         mode_ptr: i32,
+         mode_len: i32,
+         keys_ptr: i32,
+         keys_len: i32,
+         ptr: i32,
+         len: i32| {
+            match (
+                read_string(&caller, mode_ptr, mode_len),
+                read_string(&caller, keys_ptr, keys_len),
+                read_string(&caller, ptr, len),
+            ) {
+                (Some(mode), Some(keys), Some(command)) => {
+                    caller.data_mut().bindings.push(KeyBinding {
+                        mode,
+                        keys,
+                        command,
+                    });
+                    0
+                }
+                _ => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "message",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| match read_string(&caller, ptr, len)
+        {
+            Some(message) => {
+                caller
+                    .data_mut()
+                    .requests
+                    .push(PluginRequest::Message(message));
+                0
+            }
+            None => -1,
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "set_status",
+        |mut caller: Caller<'_, HostState>, id_ptr: i32, id_len: i32, ptr: i32, len: i32| {
+            let request = match (
+                read_string(&caller, id_ptr, id_len),
+                read_string(&caller, ptr, len),
+            ) {
+                (Some(id), Some(text)) => Some(PluginRequest::SetStatus { id, text }),
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "add_sign",
+        |mut caller: Caller<'_, HostState>, row: i32, ptr: i32, len: i32| {
+            let request = match (row_index(row), read_string(&caller, ptr, len)) {
+                (Some(row), Some(text)) => Some(PluginRequest::AddSign { row, text }),
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "add_virtual_text",
+        |mut caller: Caller<'_, HostState>, row: i32, ptr: i32, len: i32| {
+            let request = match (row_index(row), read_string(&caller, ptr, len)) {
+                (Some(row), Some(text)) => Some(PluginRequest::AddVirtualText { row, text }),
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "add_highlight",
+        |mut caller: Caller<'_, HostState>, row: i32, start: i32, end: i32, ptr: i32, len: i32| {
+            let request = match (
+                row_index(row),
+                row_index(start),
+                row_index(end),
+                read_string(&caller, ptr, len),
+            ) {
+                (Some(row), Some(start), Some(end), Some(group)) if start <= end => {
+                    Some(PluginRequest::AddHighlight {
+                        row,
+                        start,
+                        end,
+                        group,
+                    })
+                }
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "clear_decorations",
+        |mut caller: Caller<'_, HostState>| {
+            caller
+                .data_mut()
+                .requests
+                .push(PluginRequest::ClearDecorations);
+        },
+    )?;
+    // `argv` holds the command and its arguments, one per line
+    linker.func_wrap(
+        "loe",
+        "start_job",
+        |mut caller: Caller<'_, HostState>, id_ptr: i32, id_len: i32, ptr: i32, len: i32| {
+            let request = match (
+                read_string(&caller, id_ptr, id_len),
+                read_string(&caller, ptr, len),
+            ) {
+                (Some(id), Some(argv)) => {
+                    let mut argv = argv.lines().map(str::to_string);
+                    argv.next().map(|command| PluginRequest::StartJob {
+                        id,
+                        command,
+                        args: argv.collect(),
+                    })
+                }
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "stop_job",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let request = read_string(&caller, ptr, len).map(PluginRequest::StopJob);
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "start_timer",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32, interval: i64, repeat: i32| {
+            let request = match read_string(&caller, ptr, len) {
+                Some(id) if interval >= 0 => Some(PluginRequest::StartTimer {
+                    id,
+                    interval: interval as u64,
+                    repeat: repeat != 0,
+                }),
+                _ => None,
+            };
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "stop_timer",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let request = read_string(&caller, ptr, len).map(PluginRequest::StopTimer);
+            push_request(&mut caller, request)
+        },
+    )?;
+    linker.func_wrap(
+        "loe",
+        "log",
+        |caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            if let Some(message) = read_string(&caller, ptr, len) {
+                log!("wasm: {}", message);
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn with_buffer<R, F>(caller: &Caller<'_, HostState>, f: F) -> Option<R>
+where
+    F: FnOnce(&mut Buffer) -> R,
+{
+    unsafe { caller.data().buffer.as_mut() }.map(f)
+}
+
+fn edit_line<F>(caller: &Caller<'_, HostState>, row: i32, ptr: i32, len: i32, edit: F) -> i32
+where
+    F: FnOnce(&mut Buffer, usize, String) -> bool,
+{
+    let edited = match (row_index(row), read_string(caller, ptr, len)) {
+        (Some(row), Some(line)) => with_buffer(caller, |buffer| edit(buffer, row, line)),
+        _ => None,
+    };
+    status(edited == Some(true))
+}
+
+// queues a request of the module, `None` if its arguments were invalid
+fn push_request(caller: &mut Caller<'_, HostState>, request: Option<PluginRequest>) -> i32
+{
+    match request {
+        Some(request) => {
+            caller.data_mut().requests.push(request);
+            0
+        }
+        None => -1,
+    }
+}
+
+fn row_index(row: i32) -> Option<usize>
+{
+    if row < 0 {
+        None
+    } else {
+        Some(row as usize)
+    }
+}
+
+// strings are borrowed from the memory of the module, a range outside of it
+// or longer than `MAX_STRING_LEN` is refused before anything is copied
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<String>
+{
+    if ptr < 0 || len < 0 || len as usize > MAX_STRING_LEN {
+        return None;
+    }
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let (start, len) = (ptr as usize, len as usize);
+    let bytes = memory.data(caller).get(start..start.checked_add(len)?)?;
+    std::str::from_utf8(bytes).ok().map(str::to_string)
+}
+
+fn write_bytes(caller: &mut Caller<'_, HostState>, ptr: i32, bytes: &[u8]) -> bool
+{
+    match caller.get_export("memory").and_then(Extern::into_memory) {
+        Some(memory) if ptr >= 0 => memory.write(caller, ptr as usize, bytes).is_ok(),
+        _ => false,
+    }
+}
+
+fn status(done: bool) -> i32
+{
+    if done {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,119 @@
+// colors the view asks for and how they are reduced to what the terminal is
+// able to show
+
+use std::env;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color
+{
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    // index into the 256 color palette
+    Byte(u8),
+    Rgb(u8, u8, u8),
+}
+
+// number of colors a terminal supports, from least to most
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ColorDepth
+{
+    Ansi16,
+    EightBit,
+    TrueColor,
+}
+
+// the eight basic colors in the order of their ansi codes
+const BASIC: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+impl ColorDepth
+{
+    // `COLORTERM` announces true color support, `TERM` names like
+    // `xterm-256color` announce the 256 color palette
+    pub fn detect() -> Self
+    {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        if term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::EightBit
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+impl Color
+{
+    // replaces the color by the closest one available at `depth`. without
+    // the 256 color palette only the eight basic colors are left
+    pub fn reduce(self, depth: ColorDepth) -> Self
+    {
+        match (self, depth) {
+            (Color::Rgb(r, g, b), ColorDepth::EightBit) => Color::Byte(rgb_to_byte(r, g, b)),
+            (Color::Rgb(r, g, b), ColorDepth::Ansi16) => rgb_to_basic(r, g, b),
+            // the 16 ansi colors are the basic colors and their bright
+            // variants
+            (Color::Byte(n), ColorDepth::Ansi16) if n < 16 => BASIC[usize::from(n % 8)],
+            (Color::Byte(n), ColorDepth::Ansi16) => {
+                let (r, g, b) = byte_to_rgb(n);
+                rgb_to_basic(r, g, b)
+            }
+            (color, _) => color,
+        }
+    }
+}
+
+// the 6x6x6 color cube starts at 16, followed by 24 shades of gray
+fn rgb_to_byte(r: u8, g: u8, b: u8) -> u8
+{
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            _ => 232 + ((u16::from(r) - 8) * 24 / 241) as u8,
+        };
+    }
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn byte_to_rgb(n: u8) -> (u8, u8, u8)
+{
+    if n >= 232 {
+        let gray = 8 + (n - 232) * 10;
+        return (gray, gray, gray);
+    }
+    let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+    let n = n - 16;
+    (level(n / 36), level(n / 6 % 6), level(n % 6))
+}
+
+fn rgb_to_basic(r: u8, g: u8, b: u8) -> Color
+{
+    let bit = |v: u8, shift: usize| usize::from(v >= 128) << shift;
+    BASIC[bit(r, 0) | bit(g, 1) | bit(b, 2)]
+}
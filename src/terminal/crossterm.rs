@@ -0,0 +1,182 @@
+// a terminal backend written in pure rust, it needs no termbox library
+// installed on the system
+
+use std::io::{stdout, Stdout, Write};
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crossterm::cursor::{MoveTo, Show};
+use crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::event_loop::tty_fd;
+use crate::input::{Event, Key};
+use crate::terminal::{Color, ColorDepth, ColorPair, Position, Style, Terminal};
+
+pub struct CrosstermTerminal
+{
+    stdout: Mutex<Stdout>,
+    cursor: Mutex<Position>,
+    depth: ColorDepth,
+}
+
+impl CrosstermTerminal
+{
+    pub fn new() -> Self
+    {
+        let mut stdout = stdout();
+        if terminal::enable_raw_mode().is_err() || execute!(stdout, EnterAlternateScreen).is_err() {
+            panic!("could not initialize terminal");
+        }
+        Self {
+            stdout: Mutex::new(stdout),
+            cursor: Mutex::new((0, 0)),
+            depth: ColorDepth::detect(),
+        }
+    }
+}
+
+impl Terminal for CrosstermTerminal
+{
+    fn set_cursor(&self, x: i64, y: i64)
+    {
+        *self.cursor.lock().unwrap() = (x, y);
+    }
+
+    fn size(&self) -> (usize, usize)
+    {
+        terminal::size().map_or((0, 0), |(w, h)| (w as usize, h as usize))
+    }
+
+    fn input_fd(&self) -> RawFd
+    {
+        tty_fd()
+    }
+
+    // crossterm handles SIGWINCH itself and reports the resize on the next
+    // read
+    fn read_events(&self) -> Vec<Event>
+    {
+        let mut events = vec![];
+        while event::poll(Duration::from_millis(0)).unwrap_or(false) {
+            match event::read() {
+                Ok(event) => events.extend(convert_event(event)),
+                Err(_) => break,
+            }
+        }
+        events
+    }
+
+    fn print(&self, position: Position, style: Style, color: ColorPair, content: &str)
+    {
+        let (x, y) = position;
+        let (fg, bg) = color;
+        let mut stdout = self.stdout.lock().unwrap();
+        queue!(
+            stdout,
+            MoveTo(x as u16, y as u16),
+            SetForegroundColor(convert(fg.reduce(self.depth))),
+            SetBackgroundColor(convert(bg.reduce(self.depth)))
+        )
+        .ok();
+        for (enabled, attribute) in &[
+            (style.bold, Attribute::Bold),
+            (style.italic, Attribute::Italic),
+            (style.underline, Attribute::Underlined),
+            (style.reverse, Attribute::Reverse),
+        ] {
+            if *enabled {
+                queue!(stdout, SetAttribute(*attribute)).ok();
+            }
+        }
+        queue!(stdout, Print(content), SetAttribute(Attribute::Reset)).ok();
+    }
+
+    fn present(&self)
+    {
+        let (x, y) = *self.cursor.lock().unwrap();
+        let mut stdout = self.stdout.lock().unwrap();
+        queue!(stdout, MoveTo(x as u16, y as u16), Show).ok();
+        stdout.flush().ok();
+    }
+
+    fn clear(&self)
+    {
+        let mut stdout = self.stdout.lock().unwrap();
+        queue!(stdout, Clear(ClearType::All)).ok();
+    }
+
+    fn suspend(&self)
+    {
+        let mut stdout = self.stdout.lock().unwrap();
+        execute!(stdout, LeaveAlternateScreen, Show).ok();
+        terminal::disable_raw_mode().ok();
+    }
+
+    fn resume(&self)
+    {
+        let mut stdout = self.stdout.lock().unwrap();
+        terminal::enable_raw_mode().ok();
+        execute!(stdout, EnterAlternateScreen).ok();
+    }
+}
+
+impl Drop for CrosstermTerminal
+{
+    // leaves the terminal as it was before
+    fn drop(&mut self)
+    {
+        self.suspend();
+    }
+}
+
+fn convert_event(event: event::Event) -> Option<Event>
+{
+    match event {
+        event::Event::Key(key) if key.kind != KeyEventKind::Release => {
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Tab => Some(Event::Key(Key::Tab)),
+                KeyCode::Enter => Some(Event::Key(Key::Enter)),
+                KeyCode::Esc => Some(Event::Key(Key::Esc)),
+                KeyCode::Backspace => Some(Event::Key(Key::Backspace)),
+                KeyCode::Right => Some(Event::Key(Key::Right)),
+                KeyCode::Left => Some(Event::Key(Key::Left)),
+                KeyCode::Up => Some(Event::Key(Key::Up)),
+                KeyCode::Down => Some(Event::Key(Key::Down)),
+                KeyCode::Delete => Some(Event::Key(Key::Delete)),
+                KeyCode::Insert => Some(Event::Key(Key::Insert)),
+                KeyCode::Home => Some(Event::Key(Key::Home)),
+                KeyCode::End => Some(Event::Key(Key::End)),
+                KeyCode::PageUp => Some(Event::Key(Key::PageUp)),
+                KeyCode::PageDown => Some(Event::Key(Key::PageDown)),
+                KeyCode::Char(c) if ctrl => Some(Event::Key(Key::Ctrl(c))),
+                KeyCode::Char(c) => Some(Event::Key(Key::Char(c))),
+                _ => None,
+            }
+        }
+        event::Event::Resize(_, _) => Some(Event::Resize),
+        _ => None,
+    }
+}
+
+fn convert(color: Color) -> crossterm::style::Color
+{
+    use crossterm::style::Color as C;
+    match color {
+        Color::Default => C::Reset,
+        Color::Black => C::Black,
+        Color::Red => C::DarkRed,
+        Color::Green => C::DarkGreen,
+        Color::Yellow => C::DarkYellow,
+        Color::Blue => C::DarkBlue,
+        Color::Magenta => C::DarkMagenta,
+        Color::Cyan => C::DarkCyan,
+        Color::White => C::Grey,
+        Color::Byte(n) => C::AnsiValue(n),
+        Color::Rgb(r, g, b) => C::Rgb { r, g, b },
+    }
+}
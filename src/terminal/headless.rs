@@ -0,0 +1,204 @@
+// a terminal that only exists in memory. it keeps a grid of cells, records
+// every call of the view and replays scripted events, so the app can be run
+// without a tty:
+//
+//     let terminal = Arc::new(HeadlessTerminal::new(80, 24));
+//     terminal.push_keys("ihello<Esc>:w<CR>:q<CR>")?;
+//     App::new(config, View::with_terminal(terminal.clone())).run()?;
+//     assert!(terminal.screen()[0].contains("hello"));
+
+use std::collections::VecDeque;
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+
+use crate::event_loop::Waker;
+use crate::input::Event;
+use crate::keymap::parse_keys;
+use crate::terminal::{Cell, ColorPair, Position, Style, Terminal};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call
+{
+    SetCursor(Position),
+    Print
+    {
+        position: Position,
+        style: Style,
+        color: ColorPair,
+        content: String,
+    },
+    Present,
+    Clear,
+}
+
+struct Screen
+{
+    size: (usize, usize),
+    cells: Vec<Cell>,
+    cursor: Position,
+    calls: Vec<Call>,
+}
+
+pub struct HeadlessTerminal
+{
+    screen: Mutex<Screen>,
+    events: Mutex<VecDeque<Event>>,
+    // woken when events are queued, so the event loop reads them
+    arrived: Waker,
+}
+
+impl HeadlessTerminal
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        Self {
+            screen: Mutex::new(Screen {
+                size: (width, height),
+                cells: vec![Cell::default(); width * height],
+                cursor: (0, 0),
+                calls: vec![],
+            }),
+            events: Mutex::new(VecDeque::new()),
+            arrived: Waker::new().unwrap(),
+        }
+    }
+
+    // queues an event for `listen`
+    pub fn push_event(&self, event: Event)
+    {
+        self.events.lock().unwrap().push_back(event);
+        self.arrived.wake();
+    }
+
+    // queues key presses written in the notation of the `map` command
+    pub fn push_keys(&self, notation: &str) -> Result<(), String>
+    {
+        let keys = parse_keys(notation)?;
+        self.events
+            .lock()
+            .unwrap()
+            .extend(keys.into_iter().map(Event::Key));
+        self.arrived.wake();
+        Ok(())
+    }
+
+    // changes the size of the grid, which is cleared, and queues a resize
+    // event like a real terminal would
+    pub fn resize(&self, width: usize, height: usize)
+    {
+        {
+            let mut screen = self.screen.lock().unwrap();
+            screen.size = (width, height);
+            screen.cells = vec![Cell::default(); width * height];
+        }
+        self.push_event(Event::Resize);
+    }
+
+    // the text of every row without trailing whitespace
+    pub fn screen(&self) -> Vec<String>
+    {
+        let screen = self.screen.lock().unwrap();
+        let width = screen.size.0.max(1);
+        screen
+            .cells
+            .chunks(width)
+            .map(|row| {
+                let line = row.iter().map(|cell| cell.content).collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<Cell>
+    {
+        let screen = self.screen.lock().unwrap();
+        let (width, height) = screen.size;
+        if x < width && y < height {
+            Some(screen.cells[y * width + x].clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn cursor(&self) -> Position
+    {
+        self.screen.lock().unwrap().cursor
+    }
+
+    // returns the calls recorded since the last time this was called
+    pub fn take_calls(&self) -> Vec<Call>
+    {
+        std::mem::take(&mut self.screen.lock().unwrap().calls)
+    }
+}
+
+impl Terminal for HeadlessTerminal
+{
+    fn set_cursor(&self, x: i64, y: i64)
+    {
+        let mut screen = self.screen.lock().unwrap();
+        screen.cursor = (x, y);
+        screen.calls.push(Call::SetCursor((x, y)));
+    }
+
+    fn size(&self) -> (usize, usize)
+    {
+        self.screen.lock().unwrap().size
+    }
+
+    fn input_fd(&self) -> RawFd
+    {
+        self.arrived.fd()
+    }
+
+    // scripted events are handed out like input from a real terminal
+    fn read_events(&self) -> Vec<Event>
+    {
+        self.arrived.reset();
+        self.events.lock().unwrap().drain(..).collect()
+    }
+
+    // text outside of the grid is cut off
+    fn print(&self, position: Position, style: Style, color: ColorPair, content: &str)
+    {
+        let mut screen = self.screen.lock().unwrap();
+        let (width, height) = screen.size;
+        let (x, y) = position;
+        if y >= 0 && (y as usize) < height {
+            for (i, c) in content.chars().enumerate() {
+                let column = x + i as i64;
+                if column < 0 {
+                    continue;
+                }
+                if column as usize >= width {
+                    break;
+                }
+                screen.cells[y as usize * width + column as usize] = Cell {
+                    content: c,
+                    style,
+                    color,
+                };
+            }
+        }
+        screen.calls.push(Call::Print {
+            position,
+            style,
+            color,
+            content: content.to_string(),
+        });
+    }
+
+    fn present(&self)
+    {
+        self.screen.lock().unwrap().calls.push(Call::Present);
+    }
+
+    fn clear(&self)
+    {
+        let mut screen = self.screen.lock().unwrap();
+        for cell in screen.cells.iter_mut() {
+            *cell = Cell::default();
+        }
+        screen.calls.push(Call::Clear);
+    }
+}
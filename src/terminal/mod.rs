@@ -1,19 +1,74 @@
+mod color;
+pub mod crossterm;
+#[cfg(test)]
+pub mod headless;
 pub mod rustbox;
 
-use self::rustbox::RustBoxColor;
-use self::rustbox::RustBoxStyle;
+use std::os::unix::io::RawFd;
+
+use serde_derive::Deserialize;
+
 use crate::input::Event;
 
+pub use self::color::{Color, ColorDepth};
+
 pub type Position = (i64, i64);
-pub type Color = (RustBoxColor, RustBoxColor);
-pub type Style = RustBoxStyle;
+// foreground and background
+pub type ColorPair = (Color, Color);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Style
+{
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+// one character on the screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell
+{
+    pub content: char,
+    pub style: Style,
+    pub color: ColorPair,
+}
+
+impl Default for Cell
+{
+    fn default() -> Self
+    {
+        Self {
+            content: ' ',
+            style: Style::default(),
+            color: (Color::Default, Color::Default),
+        }
+    }
+}
+
+// the implementation of `Terminal` used by the view
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend
+{
+    #[default]
+    Rustbox,
+    Crossterm,
+}
 
 pub trait Terminal
 {
     fn set_cursor(&self, x: i64, y: i64);
     fn size(&self) -> (usize, usize);
-    fn listen(&self) -> Option<Event>;
-    fn print(&self, position: Position, style: Style, color: Color, content: &str);
+    // becomes readable when there is input, the event loop waits on it
+    fn input_fd(&self) -> RawFd;
+    // the events that can be read without blocking
+    fn read_events(&self) -> Vec<Event>;
+    fn print(&self, position: Position, style: Style, color: ColorPair, content: &str);
     fn present(&self);
     fn clear(&self);
+
+    // gives the terminal back to the shell while the app is stopped
+    fn suspend(&self) {}
+    fn resume(&self) {}
 }
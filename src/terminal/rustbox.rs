@@ -1,34 +1,64 @@
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use rustbox::Key as RustBoxKey;
-use rustbox::{Color, OutputMode, RustBox, Style};
+use rustbox::{OutputMode, RustBox};
 
+use crate::event_loop::tty_fd;
 use crate::input::{Event, Key};
-use crate::terminal::{Position, Terminal};
-
-pub type RustBoxColor = Color;
-pub type RustBoxStyle = Style;
+use crate::terminal::{Color, ColorDepth, ColorPair, Position, Style, Terminal};
 
+// termbox cannot give up the terminal itself. while the app is suspended the
+// settings from before termbox took over are put back by hand
 pub struct RustBoxTerminal
 {
     rustbox: RustBox,
-    timeout: Duration,
+    // termbox knows no true color, so this is at most `EightBit`
+    depth: ColorDepth,
+    cooked: Option<libc::termios>,
+    // the settings of termbox while they are swapped out
+    raw: Mutex<Option<libc::termios>>,
 }
 
 impl RustBoxTerminal
 {
     pub fn new() -> Self
     {
+        let depth = ColorDepth::detect().min(ColorDepth::EightBit);
+        let cooked = termios();
         Self {
-            timeout: Duration::from_millis(100),
             rustbox: {
                 if let Ok(mut rb) = RustBox::init(Default::default()) {
-                    rb.set_output_mode(OutputMode::EightBit);
+                    rb.set_output_mode(match depth {
+                        ColorDepth::Ansi16 => OutputMode::Normal,
+                        _ => OutputMode::EightBit,
+                    });
                     rb
                 } else {
                     panic!("could not initialize rustbox");
                 }
             },
+            depth,
+            cooked,
+            raw: Mutex::new(None),
+        }
+    }
+
+    fn convert(&self, color: Color) -> rustbox::Color
+    {
+        match color.reduce(self.depth) {
+            Color::Default => rustbox::Color::Default,
+            Color::Black => rustbox::Color::Black,
+            Color::Red => rustbox::Color::Red,
+            Color::Green => rustbox::Color::Green,
+            Color::Yellow => rustbox::Color::Yellow,
+            Color::Blue => rustbox::Color::Blue,
+            Color::Magenta => rustbox::Color::Magenta,
+            Color::Cyan => rustbox::Color::Cyan,
+            Color::White => rustbox::Color::White,
+            Color::Byte(n) => rustbox::Color::Byte(u16::from(n)),
+            Color::Rgb(_, _, _) => unreachable!("rgb colors are reduced"),
         }
     }
 }
@@ -45,40 +75,48 @@ impl Terminal for RustBoxTerminal
         (self.rustbox.width(), self.rustbox.height())
     }
 
-    fn listen(&self) -> Option<Event>
+    fn input_fd(&self) -> RawFd
     {
-        match self.rustbox.peek_event(self.timeout, false) {
-            Ok(rustbox::Event::KeyEvent(key)) => match key {
-                RustBoxKey::Tab => Some(Event::Key(Key::Tab)),
-                RustBoxKey::Enter => Some(Event::Key(Key::Enter)),
-                RustBoxKey::Esc => Some(Event::Key(Key::Esc)),
-                RustBoxKey::Backspace => Some(Event::Key(Key::Backspace)),
-                RustBoxKey::Right => Some(Event::Key(Key::Right)),
-                RustBoxKey::Left => Some(Event::Key(Key::Left)),
-                RustBoxKey::Up => Some(Event::Key(Key::Up)),
-                RustBoxKey::Down => Some(Event::Key(Key::Down)),
-                RustBoxKey::Delete => Some(Event::Key(Key::Delete)),
-                RustBoxKey::Insert => Some(Event::Key(Key::Insert)),
-                RustBoxKey::Home => Some(Event::Key(Key::Home)),
-                RustBoxKey::End => Some(Event::Key(Key::End)),
-                RustBoxKey::PageUp => Some(Event::Key(Key::PageUp)),
-                RustBoxKey::PageDown => Some(Event::Key(Key::PageDown)),
-                RustBoxKey::Char(c) => Some(Event::Key(Key::Char(c))),
-                RustBoxKey::Ctrl(c) => Some(Event::Key(Key::Ctrl(c))),
-                _ => None,
-            },
-            Ok(rustbox::Event::ResizeEvent(_, _)) => Some(Event::Resize),
-            _ => None,
+        tty_fd()
+    }
+
+    // termbox handles SIGWINCH itself and reports the resize on the next read
+    fn read_events(&self) -> Vec<Event>
+    {
+        let mut events = vec![];
+        loop {
+            let event = match self.rustbox.peek_event(Duration::from_millis(0), false) {
+                Ok(rustbox::Event::NoEvent) | Err(_) => break,
+                Ok(event) => event,
+            };
+            events.extend(convert_event(event));
         }
+        events
     }
 
-    fn print(&self, position: Position, _style: Style, color: crate::terminal::Color, content: &str)
+    // termbox cannot show italic text
+    fn print(&self, position: Position, style: Style, color: ColorPair, content: &str)
     {
-        let style = rustbox::Style::empty();
+        let mut attributes = rustbox::RB_NORMAL;
+        for (enabled, attribute) in &[
+            (style.bold, rustbox::RB_BOLD),
+            (style.underline, rustbox::RB_UNDERLINE),
+            (style.reverse, rustbox::RB_REVERSE),
+        ] {
+            if *enabled {
+                attributes = attributes | *attribute;
+            }
+        }
         let (fg, bg) = color;
         let (x, y) = position;
-        self.rustbox
-            .print(x as usize, y as usize, style, fg, bg, content);
+        self.rustbox.print(
+            x as usize,
+            y as usize,
+            attributes,
+            self.convert(fg),
+            self.convert(bg),
+            content,
+        );
     }
 
     fn present(&self)
@@ -90,4 +128,64 @@ impl Terminal for RustBoxTerminal
     {
         self.rustbox.clear();
     }
+
+    // leaves the alternate screen and restores the settings of the shell
+    fn suspend(&self)
+    {
+        if let Some(cooked) = &self.cooked {
+            *self.raw.lock().unwrap() = termios();
+            write_tty(b"\x1b[?25h\x1b[?1049l");
+            unsafe { libc::tcsetattr(tty_fd(), libc::TCSADRAIN, cooked) };
+        }
+    }
+
+    fn resume(&self)
+    {
+        if let Some(raw) = self.raw.lock().unwrap().take() {
+            unsafe { libc::tcsetattr(tty_fd(), libc::TCSADRAIN, &raw) };
+            write_tty(b"\x1b[?1049h");
+        }
+    }
+}
+
+fn termios() -> Option<libc::termios>
+{
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(tty_fd(), &mut termios) } == 0 {
+        Some(termios)
+    } else {
+        None
+    }
+}
+
+fn write_tty(bytes: &[u8])
+{
+    unsafe { libc::write(tty_fd(), bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+}
+
+fn convert_event(event: rustbox::Event) -> Option<Event>
+{
+    match event {
+        rustbox::Event::KeyEvent(key) => match key {
+            RustBoxKey::Tab => Some(Event::Key(Key::Tab)),
+            RustBoxKey::Enter => Some(Event::Key(Key::Enter)),
+            RustBoxKey::Esc => Some(Event::Key(Key::Esc)),
+            RustBoxKey::Backspace => Some(Event::Key(Key::Backspace)),
+            RustBoxKey::Right => Some(Event::Key(Key::Right)),
+            RustBoxKey::Left => Some(Event::Key(Key::Left)),
+            RustBoxKey::Up => Some(Event::Key(Key::Up)),
+            RustBoxKey::Down => Some(Event::Key(Key::Down)),
+            RustBoxKey::Delete => Some(Event::Key(Key::Delete)),
+            RustBoxKey::Insert => Some(Event::Key(Key::Insert)),
+            RustBoxKey::Home => Some(Event::Key(Key::Home)),
+            RustBoxKey::End => Some(Event::Key(Key::End)),
+            RustBoxKey::PageUp => Some(Event::Key(Key::PageUp)),
+            RustBoxKey::PageDown => Some(Event::Key(Key::PageDown)),
+            RustBoxKey::Char(c) => Some(Event::Key(Key::Char(c))),
+            RustBoxKey::Ctrl(c) => Some(Event::Key(Key::Ctrl(c))),
+            _ => None,
+        },
+        rustbox::Event::ResizeEvent(_, _) => Some(Event::Resize),
+        _ => None,
+    }
 }
@@ -0,0 +1,251 @@
+// color themes assign colors and attributes to named highlight groups.
+// groups are nested with dots, e.g. `status.insert` is the status line in
+// insert mode. a group takes everything it does not set from its parent and
+// finally from `normal`
+
+use std::collections::HashMap;
+use std::fs;
+
+use toml::value::{Table, Value};
+
+use crate::config::{expand_path, THEME_DIR_PATH};
+use crate::terminal::{Color, ColorPair, Style};
+
+pub const DEFAULT_THEME: &str = "default";
+
+const BUNDLED_THEMES: &[(&str, &str)] = &[
+    ("default", include_str!("../themes/default.toml")),
+    ("light", include_str!("../themes/light.toml")),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Group
+{
+    pub color: ColorPair,
+    pub style: Style,
+}
+
+// a group as written in the theme file, unset fields are inherited
+#[derive(Clone, Debug, Default)]
+struct GroupSpec
+{
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: Option<bool>,
+    italic: Option<bool>,
+    underline: Option<bool>,
+    reverse: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme
+{
+    name: String,
+    groups: HashMap<String, GroupSpec>,
+}
+
+impl Theme
+{
+    // themes in the config directory take precedence over bundled themes of
+    // the same name
+    pub fn load(name: &str) -> Result<Self, String>
+    {
+        let path = expand_path(THEME_DIR_PATH).join(format!("{}.toml", name));
+        if path.exists() {
+            let source = fs::read_to_string(&path)
+                .map_err(|err| format!("could not read `{}`: {}", path.display(), err))?;
+            return Self::parse(name, &source)
+                .map_err(|err| format!("invalid colorscheme `{}`: {}", path.display(), err));
+        }
+        match bundled(name) {
+            Some(source) => Self::parse(name, source),
+            None => Err(format!("unknown colorscheme `{}`", name)),
+        }
+    }
+
+    pub fn parse(name: &str, source: &str) -> Result<Self, String>
+    {
+        let table: Table = toml::from_str(source).map_err(|err| err.to_string())?;
+        let mut groups = HashMap::new();
+        parse_groups(&table, "", &mut groups)?;
+        Ok(Self {
+            name: name.to_string(),
+            groups,
+        })
+    }
+
+    // bundled themes and those in the config directory, sorted by name
+    pub fn available() -> Vec<String>
+    {
+        let mut names = BUNDLED_THEMES
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        if let Ok(entries) = fs::read_dir(expand_path(THEME_DIR_PATH)) {
+            for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+                if path.extension().is_some_and(|ext| ext == "toml") {
+                    if let Some(stem) = path.file_stem() {
+                        names.push(stem.to_string_lossy().to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    // the group or `normal` if neither the group nor one of its parents is
+    // defined
+    pub fn group(&self, name: &str) -> Group
+    {
+        self.lookup(name).unwrap_or_else(|| self.resolve(&[]))
+    }
+
+    // returns `None` if neither the group nor one of its parents is defined
+    pub fn lookup(&self, name: &str) -> Option<Group>
+    {
+        let mut path = vec![];
+        let mut prefix = String::new();
+        for part in name.split('.') {
+            if !prefix.is_empty() {
+                prefix.push('.');
+            }
+            prefix.push_str(part);
+            if let Some(spec) = self.groups.get(&prefix) {
+                path.push(spec);
+            }
+        }
+        if path.is_empty() {
+            None
+        } else {
+            Some(self.resolve(&path))
+        }
+    }
+
+    // applies the specs from the most general to the most specific one on
+    // top of `normal`
+    fn resolve(&self, path: &[&GroupSpec]) -> Group
+    {
+        let mut group = Group {
+            color: (Color::Default, Color::Default),
+            style: Style::default(),
+        };
+        for spec in self
+            .groups
+            .get("normal")
+            .into_iter()
+            .chain(path.iter().cloned())
+        {
+            group.color.0 = spec.fg.unwrap_or(group.color.0);
+            group.color.1 = spec.bg.unwrap_or(group.color.1);
+            group.style.bold = spec.bold.unwrap_or(group.style.bold);
+            group.style.italic = spec.italic.unwrap_or(group.style.italic);
+            group.style.underline = spec.underline.unwrap_or(group.style.underline);
+            group.style.reverse = spec.reverse.unwrap_or(group.style.reverse);
+        }
+        group
+    }
+}
+
+impl Default for Theme
+{
+    // the bundled default theme, never the one of the user. it is what the
+    // view falls back to if a theme cannot be loaded
+    fn default() -> Self
+    {
+        bundled(DEFAULT_THEME)
+            .and_then(|source| Self::parse(DEFAULT_THEME, source).ok())
+            .expect("the bundled default theme is invalid")
+    }
+}
+
+fn bundled(name: &str) -> Option<&'static str>
+{
+    BUNDLED_THEMES
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, source)| *source)
+}
+
+// every table is a group, tables inside of it are its children
+fn parse_groups(
+    table: &Table,
+    prefix: &str,
+    groups: &mut HashMap<String, GroupSpec>,
+) -> Result<(), String>
+{
+    let mut spec = GroupSpec::default();
+    for (key, value) in table {
+        let name = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        let color = || {
+            parse_color(value)
+                .map(Some)
+                .map_err(|err| format!("`{}`: {}", name, err))
+        };
+        let flag = || {
+            value
+                .as_bool()
+                .map(Some)
+                .ok_or_else(|| format!("`{}` has to be true or false", name))
+        };
+        match (key.as_ref(), value) {
+            (_, Value::Table(children)) => parse_groups(children, &name, groups)?,
+            _ if prefix.is_empty() => return Err(format!("`{}` is not a group", name)),
+            ("fg", _) => spec.fg = color()?,
+            ("bg", _) => spec.bg = color()?,
+            ("bold", _) => spec.bold = flag()?,
+            ("italic", _) => spec.italic = flag()?,
+            ("underline", _) => spec.underline = flag()?,
+            ("reverse", _) => spec.reverse = flag()?,
+            _ => return Err(format!("unknown attribute `{}`", name)),
+        }
+    }
+    if !prefix.is_empty() {
+        groups.insert(prefix.to_string(), spec);
+    }
+    Ok(())
+}
+
+// colors are names, indices into the 256 color palette or `#rrggbb`
+fn parse_color(value: &Value) -> Result<Color, String>
+{
+    if let Some(n) = value.as_integer() {
+        return match n {
+            0..=255 => Ok(Color::Byte(n as u8)),
+            _ => Err(format!("color {} is out of range", n)),
+        };
+    }
+    let name = value
+        .as_str()
+        .ok_or_else(|| format!("invalid color {}", value))?;
+    let color = match name {
+        "default" => Color::Default,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ if name.starts_with('#') && name.len() == 7 && name.is_ascii() => {
+            let channel = |i: usize| {
+                u8::from_str_radix(&name[i..i + 2], 16)
+                    .map_err(|_| format!("invalid color `{}`", name))
+            };
+            Color::Rgb(channel(1)?, channel(3)?, channel(5)?)
+        }
+        _ => return Err(format!("invalid color `{}`", name)),
+    };
+    Ok(color)
+}
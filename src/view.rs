@@ -1,26 +1,83 @@
 use std::sync::Arc;
 use std::vec::IntoIter;
 
+use crate::decoration::{Decorations, SIGN_WIDTH};
+use crate::terminal::crossterm::CrosstermTerminal;
 use crate::terminal::rustbox::RustBoxTerminal;
-use crate::terminal::{Style, Terminal};
+use crate::terminal::{Backend, Cell, ColorPair, Position, Style, Terminal};
+use crate::theme::{Group, Theme};
 
-const STYLE_NORMAL: Style = rustbox::RB_NORMAL;
+pub struct RenderOptions<'a>
+{
+    pub number: bool,
+    pub relativenumber: bool,
+    pub list: bool,
+    // long lines continue on the next rows instead of being cut off
+    pub wrap: bool,
+    pub cursor_row: usize,
+    pub decorations: &'a Decorations,
+}
 
+// everything is drawn into `frame` first. `present` compares it with the
+// frame shown before and only sends the rows that changed to the terminal
 pub struct View
 {
-    terminal: Arc<Terminal + Sync + Send>,
+    terminal: Arc<dyn Terminal + Sync + Send>,
+    theme: Theme,
+    frame: Frame,
+    shown: Option<Frame>,
 }
 
 impl View
 {
-    pub fn new() -> Self
+    pub fn new(backend: Backend) -> Self
+    {
+        let terminal: Arc<dyn Terminal + Sync + Send> = match backend {
+            Backend::Rustbox => Arc::new(RustBoxTerminal::new()),
+            Backend::Crossterm => Arc::new(CrosstermTerminal::new()),
+        };
+        Self::with_terminal(terminal)
+    }
+
+    pub fn with_terminal(terminal: Arc<dyn Terminal + Sync + Send>) -> Self
     {
         Self {
-            terminal: Arc::new(RustBoxTerminal::new()),
+            terminal,
+            theme: Theme::default(),
+            frame: Frame::new((0, 0), Cell::default()),
+            shown: None,
         }
     }
 
-    pub fn terminal(&self) -> Arc<Terminal + Sync + Send>
+    pub fn theme(&self) -> &Theme
+    {
+        &self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme)
+    {
+        self.theme = theme;
+    }
+
+    // forgets what is on the screen, the next frame is drawn completely
+    pub fn invalidate(&mut self)
+    {
+        self.shown = None;
+    }
+
+    pub fn suspend(&mut self)
+    {
+        self.terminal.suspend();
+    }
+
+    // the screen has to be drawn again after the shell used it
+    pub fn resume(&mut self)
+    {
+        self.terminal.resume();
+        self.invalidate();
+    }
+
+    pub fn terminal(&self) -> Arc<dyn Terminal + Sync + Send>
     {
         Arc::clone(&self.terminal)
     }
@@ -30,14 +87,48 @@ impl View
         self.terminal.size()
     }
 
+    // starts a new frame filled with the background of `normal`
     pub fn clear(&mut self)
     {
-        self.terminal.clear();
+        let normal = self.theme.group("normal");
+        let blank = Cell {
+            content: ' ',
+            style: normal.style,
+            color: normal.color,
+        };
+        self.frame = Frame::new(self.terminal.size(), blank);
     }
 
     pub fn present(&mut self)
     {
+        let shown = match self.shown.take() {
+            Some(shown) if shown.size == self.frame.size => Some(shown),
+            _ => {
+                self.terminal.clear();
+                None
+            }
+        };
+        for y in 0..self.frame.size.1 {
+            let row = self.frame.row(y);
+            if shown.as_ref().is_some_and(|shown| shown.row(y) == row) {
+                continue;
+            }
+            // cells with the same colors are printed together
+            let mut x = 0;
+            while x < row.len() {
+                let (style, color) = (row[x].style, row[x].color);
+                let start = x;
+                let mut text = String::new();
+                while x < row.len() && row[x].style == style && row[x].color == color {
+                    text.push(row[x].content);
+                    x += 1;
+                }
+                self.terminal
+                    .print((start as i64, y as i64), style, color, &text);
+            }
+        }
         self.terminal.present();
+        self.shown = Some(self.frame.clone());
     }
 
     pub fn set_cursor(&mut self, x: i64, y: i64)
@@ -45,50 +136,317 @@ impl View
         self.terminal.set_cursor(x, y);
     }
 
-    pub fn render_status(&mut self, _cursor: (i64, i64), row: i64, status_text: &str)
+    // `segments` contributed by plugins are aligned to the right. the colors
+    // are those of the group `status.<mode>`
+    pub fn render_status(
+        &mut self,
+        _cursor: (i64, i64),
+        row: i64,
+        mode: &str,
+        status_text: &str,
+        segments: &[&str],
+    )
     {
-        let status_color = (rustbox::Color::Black, rustbox::Color::Red);
-        self.terminal
-            .print((0, row), STYLE_NORMAL, status_color, &status_text);
+        let status = self.theme.group(&format!("status.{}", mode));
+        self.frame
+            .print((0, row), status.style, status.color, status_text);
 
         {
             let status_len = status_text.len();
-            let padding_len = self.terminal.size().0 - status_len;
+            let padding_len = self.frame.size.0 - status_len;
 
             let padding_text = (0..padding_len).map(|_| " ").collect::<String>();
-            self.terminal.print(
+            self.frame.print(
                 (status_len as i64, row),
-                STYLE_NORMAL,
-                status_color,
+                status.style,
+                status.color,
                 &padding_text,
             );
         }
+
+        if !segments.is_empty() {
+            let segments = segments.join(" | ");
+            let width = self.frame.size.0;
+            let len = segments.chars().count();
+            if status_text.len() + len < width {
+                self.frame.print(
+                    ((width - len) as i64, row),
+                    status.style,
+                    status.color,
+                    &segments,
+                );
+            }
+        }
+    }
+
+    pub fn render_messages(&mut self, row: usize, messages: &[String])
+    {
+        let message_group = self.theme.group("message");
+        for (i, message) in messages.iter().enumerate() {
+            self.frame.print(
+                (0, (row + i) as i64),
+                message_group.style,
+                message_group.color,
+                message,
+            );
+        }
     }
 
     pub fn render_buffer(
         &mut self,
         lines: IntoIter<(usize, Option<&str>)>,
         area: (usize, usize, usize, usize),
+        options: &RenderOptions,
     )
     {
-        let color = (rustbox::Color::White, rustbox::Color::Black);
+        let normal = self.theme.group("normal");
+        let line_number = self.theme.group("line-number");
+        let virtual_text_group = self.theme.group("virtual-text");
+        let sign_group = self.theme.group("sign");
+        let decorations = options.decorations;
+        let sign_width = decorations.sign_width();
+        let width = area.2.saturating_sub(area.0).max(1);
+        let bottom = area.1 + area.3;
+        let mut row = area.1;
         for (i, line) in lines {
-            if line.is_none() {
+            if line.is_none() || row >= bottom {
                 break;
             }
-            self.terminal.print(
-                (area.0 as i64, i as i64),
-                STYLE_NORMAL,
-                color,
-                line.unwrap(),
-            );
+            let line = line.unwrap();
+            let visible = visible_text(line, options.list);
+            let print = |frame: &mut Frame, column: usize, group: &Group, text: &str| {
+                for (dy, x, text) in segments(column, text, width, options.wrap) {
+                    if row + dy < bottom {
+                        let position = ((area.0 + x) as i64, (row + dy) as i64);
+                        frame.print(position, group.style, group.color, &text);
+                    }
+                }
+            };
+            print(&mut self.frame, 0, &normal, &visible);
+            // highlight columns refer to the buffer content, which is only
+            // shown as is without `list`
+            if !options.list {
+                for highlight in decorations.highlights(i) {
+                    let group = self.highlight_group(&highlight.group);
+                    let text = line
+                        .chars()
+                        .skip(highlight.start)
+                        .take(highlight.end.saturating_sub(highlight.start))
+                        .collect::<String>();
+                    print(&mut self.frame, highlight.start, &group, &text);
+                }
+            }
+            let virtual_text = decorations.virtual_text(i);
+            if !virtual_text.is_empty() {
+                let column = visible.chars().count() + 1;
+                print(
+                    &mut self.frame,
+                    column,
+                    &virtual_text_group,
+                    &virtual_text.join(" "),
+                );
+            }
+            // sign and number are shown on the first row of a wrapped line
+            if let Some(sign) = decorations.sign(i) {
+                let sign = sign.chars().take(SIGN_WIDTH).collect::<String>();
+                self.frame
+                    .print((0, row as i64), sign_group.style, sign_group.color, &sign);
+            }
             // line number
-            self.terminal.print(
-                (0, i as i64),
-                STYLE_NORMAL,
+            let number = if options.relativenumber && i != options.cursor_row {
+                (i as i64 - options.cursor_row as i64).unsigned_abs() as usize
+            } else if options.relativenumber && !options.number {
+                0
+            } else {
+                i
+            };
+            if options.number || options.relativenumber {
+                self.frame.print(
+                    (sign_width as i64, row as i64),
+                    line_number.style,
+                    line_number.color,
+                    format!(" {}", number).as_ref(),
+                );
+            }
+            row += if options.wrap {
+                screen_rows(line, width, options.list)
+            } else {
+                1
+            };
+        }
+    }
+
+    // highlight groups of plugins the theme does not define use `highlight`
+    fn highlight_group(&self, name: &str) -> Group
+    {
+        self.theme
+            .lookup(name)
+            .unwrap_or_else(|| self.theme.group("highlight"))
+    }
+}
+
+// how many rows of `width` columns a buffer line takes up when it is wrapped
+pub fn screen_rows(line: &str, width: usize, list: bool) -> usize
+{
+    let width = width.max(1);
+    let len = visible_text(line, list).chars().count();
+    len.div_ceil(width).max(1)
+}
+
+fn visible_text(line: &str, list: bool) -> String
+{
+    if list {
+        format!("{}$", line.replace('\t', "^I"))
+    } else {
+        line.to_string()
+    }
+}
+
+// splits `text`, which starts at `column` of a line, into the pieces shown
+// on each row as `(row offset, column, piece)`. without `wrap` everything
+// stays on the first row and is cut off by the frame
+fn segments(column: usize, text: &str, width: usize, wrap: bool) -> Vec<(usize, usize, String)>
+{
+    if !wrap {
+        return vec![(0, column, text.to_string())];
+    }
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut segments = vec![];
+    let mut column = column;
+    let mut rest = &chars[..];
+    while !rest.is_empty() {
+        let len = (width - column % width).min(rest.len());
+        segments.push((column / width, column % width, rest[..len].iter().collect()));
+        column += len;
+        rest = &rest[len..];
+    }
+    segments
+}
+
+#[derive(Clone)]
+struct Frame
+{
+    size: (usize, usize),
+    cells: Vec<Cell>,
+}
+
+impl Frame
+{
+    fn new(size: (usize, usize), blank: Cell) -> Self
+    {
+        Self {
+            size,
+            cells: vec![blank; size.0 * size.1],
+        }
+    }
+
+    fn row(&self, y: usize) -> &[Cell]
+    {
+        &self.cells[y * self.size.0..(y + 1) * self.size.0]
+    }
+
+    // text outside of the frame is cut off
+    fn print(&mut self, position: Position, style: Style, color: ColorPair, content: &str)
+    {
+        let (width, height) = self.size;
+        let (x, y) = position;
+        if y < 0 || y as usize >= height {
+            return;
+        }
+        for (i, c) in content.chars().enumerate() {
+            let column = x + i as i64;
+            if column < 0 {
+                continue;
+            }
+            if column as usize >= width {
+                break;
+            }
+            self.cells[y as usize * width + column as usize] = Cell {
+                content: c,
+                style,
                 color,
-                format!(" {}", i).as_ref(),
-            );
+            };
         }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::terminal::headless::{Call, HeadlessTerminal};
+
+    fn draw(view: &mut View, messages: &[&str])
+    {
+        let messages = messages.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        view.clear();
+        view.render_messages(0, &messages);
+        view.present();
+    }
+
+    fn printed_rows(calls: Vec<Call>) -> Vec<i64>
+    {
+        calls
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Print { position, .. } => Some(position.1),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn presents_only_changed_rows()
+    {
+        let terminal = Arc::new(HeadlessTerminal::new(10, 3));
+        let mut view = View::with_terminal(terminal.clone());
+        draw(&mut view, &["one", "two"]);
+        assert_eq!(terminal.screen(), vec!["one", "two", ""]);
+        assert_eq!(terminal.cell(0, 0).map(|cell| cell.content), Some('o'));
+        terminal.take_calls();
+
+        draw(&mut view, &["one", "three"]);
+        assert_eq!(printed_rows(terminal.take_calls()), vec![1]);
+        assert_eq!(terminal.screen()[1], "three");
+
+        // a new size draws everything again
+        terminal.resize(12, 3);
+        draw(&mut view, &["one", "three"]);
+        let calls = terminal.take_calls();
+        assert!(calls.contains(&Call::Clear));
+        assert_eq!(printed_rows(calls), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn wraps_long_lines()
+    {
+        let terminal = Arc::new(HeadlessTerminal::new(6, 4));
+        let mut view = View::with_terminal(terminal.clone());
+        let decorations = Decorations::new();
+        let mut options = RenderOptions {
+            number: false,
+            relativenumber: false,
+            list: false,
+            wrap: true,
+            cursor_row: 0,
+            decorations: &decorations,
+        };
+        let lines = vec![(0, Some("abcdefghij")), (1, Some("xy")), (2, Some("z"))];
+        let mut draw = |options: &RenderOptions| {
+            view.clear();
+            view.render_buffer(lines.clone().into_iter(), (1, 0, 6, 3), options);
+            view.present();
+        };
+
+        draw(&options);
+        assert_eq!(terminal.screen(), vec![" abcde", " fghij", " xy", ""]);
+        assert_eq!(screen_rows("abcdefghij", 5, false), 2);
+        assert_eq!(screen_rows("", 5, false), 1);
+
+        options.wrap = false;
+        draw(&options);
+        assert_eq!(terminal.screen(), vec![" abcde", " xy", " z", ""]);
+    }
+}